@@ -0,0 +1,37 @@
+use std::time::{Duration, Instant};
+
+//Через сколько миллисекунд после обрыва связи повторяется первая попытка переподключиться.
+//Каждая следующая попытка ждет в два раза дольше, но не дольше MAX_DELAY_IN_MILLIS
+const INITIAL_DELAY_IN_MILLIS: u64 = 500;
+const MAX_DELAY_IN_MILLIS: u64 = 30_000;
+
+//Расписание попыток переподключиться к серверу. Частые попытки нужны чтобы быстро вернуться
+// после короткого обрыва, а растущая пауза чтобы не заваливать запросами сервер который долго лежит
+#[derive(Debug)]
+pub struct Backoff {
+    //Сколько ждать после следующей попытки
+    delay: Duration,
+    //Когда пора делать следующую попытку
+    next: Instant,
+}
+
+impl Backoff {
+    //Первая попытка делается сразу
+    pub fn new() -> Backoff {
+        Backoff {
+            delay: Duration::from_millis(INITIAL_DELAY_IN_MILLIS),
+            next: Instant::now(),
+        }
+    }
+
+    //Пора ли сделать очередную попытку. Если пора то следующая откладывается
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next {
+            return false;
+        }
+        self.next = now + self.delay;
+        self.delay = (self.delay * 2).min(Duration::from_millis(MAX_DELAY_IN_MILLIS));
+        true
+    }
+}
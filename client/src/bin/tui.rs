@@ -0,0 +1,442 @@
+//Терминальный клиент чата. Работает через тот же ChatClient что и окно приложения,
+// поэтому им можно пользоваться по SSH и там где нет графической среды
+use std::env;
+use std::io::{self, Write};
+use std::net::UdpSocket;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use client::{ChatClient, ClientEvent, ConnectionState};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+
+const USAGE: &str = "Usage: client-tui <server address> <nickname> [local port or address] [--password] [--pin <server fingerprint>]";
+//Сколько миллисекунд ждать нажатия клавиши прежде чем проверить не пришли ли события от сервера
+const INPUT_POLL_IN_MILLIS: u64 = 100;
+//На сколько строк прокручивается история по PageUp и PageDown
+const SCROLL_STEP: usize = 10;
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    //С --pin подключаемся только к серверу ключ которого имеет этот отпечаток
+    let pin = match args.iter().position(|arg| arg == "--pin") {
+        Some(index) if index + 1 < args.len() => {
+            let pin = args.remove(index + 1).parse().unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(2)
+            });
+            args.remove(index);
+            Some(pin)
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            process::exit(2)
+        }
+        None => None,
+    };
+    //С --password спрашиваем пароль перед подключением. Без него входим с пустым паролем
+    let ask_password = match args.iter().position(|arg| arg == "--password") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    let password = if ask_password {
+        read_password().unwrap_or_else(|e| {
+            eprintln!("can't read password: {}", e);
+            process::exit(1)
+        })
+    } else {
+        String::new()
+    };
+    //Если локальный порт не указан то система выберет любой свободный
+    let local = args.get(2).map(String::as_str).unwrap_or("0");
+    let client = ChatClient::connect_pinned(local, &args[0], &args[1], &password, pin).unwrap_or_else(|e| {
+        eprintln!("can't connect to {}: {}", args[0], e);
+        process::exit(1)
+    });
+    let mut app = App::new(args[1].trim());
+    //Показываем отпечаток сервера чтобы пользователь мог сверить его и закрепить через --pin
+    if let Some(fingerprint) = client.server_fingerprint() {
+        app.push(format!("* server fingerprint {}", fingerprint), false);
+    }
+    let reader = client.reader().unwrap_or_else(|e| {
+        eprintln!("can't clone socket: {}", e);
+        process::exit(1)
+    });
+    let client = Arc::new(Mutex::new(client));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (events, reader) = start_reader_thread(reader, client.clone(), stop.clone());
+
+    let mut out = io::stdout();
+    let result = terminal::enable_raw_mode()
+        .and_then(|_| execute!(out, EnterAlternateScreen))
+        .and_then(|_| run(&mut out, &client, &events, app));
+    //Возвращаем терминал в обычный режим даже если что то пошло не так
+    let _ = execute!(out, LeaveAlternateScreen, cursor::Show);
+    let _ = terminal::disable_raw_mode();
+
+    //Останавливаем чтение из сокета и сообщаем серверу что мы уходим
+    stop.store(true, Ordering::SeqCst);
+    let _ = reader.join();
+    if let Ok(client) = Arc::try_unwrap(client) {
+        let _ = client.into_inner().map(|client| client.disconnect());
+    }
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+//Спрашивает пароль не показывая вводимые символы. Esc или Ctrl-C отменяют вход
+fn read_password() -> io::Result<String> {
+    let mut out = io::stdout();
+    execute!(out, Print("password: "))?;
+    terminal::enable_raw_mode()?;
+    let mut password = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Esc => break Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled")),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"))
+                }
+                KeyCode::Char(c) => password.push(c),
+                KeyCode::Backspace => {
+                    password.pop();
+                }
+                _ => {}
+            },
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+    };
+    let _ = terminal::disable_raw_mode();
+    println!();
+    result.map(|_| password)
+}
+
+//Запускает поток который читает датаграммы от сервера и превращает их в события чата
+fn start_reader_thread(socket: UdpSocket,
+                       client: Arc<Mutex<ChatClient>>,
+                       stop: Arc<AtomicBool>) -> (Receiver<ClientEvent>, JoinHandle<()>) {
+    let (sx, rx) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        while !stop.load(Ordering::SeqCst) {
+            //Ждем датаграмму не держа мьютекс, чтобы в это время можно было отправлять сообщения
+            let datagram = client::read_datagram(&socket);
+            let events = match client.lock() {
+                Ok(mut client) => client.process(datagram.as_deref()),
+                Err(_) => return,
+            };
+            for event in events {
+                if sx.send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    (rx, handle)
+}
+
+//Цикл обработки нажатий клавиш и событий от сервера. Заканчивается когда пользователь выходит
+fn run<W: Write>(out: &mut W,
+                 client: &Mutex<ChatClient>,
+                 events: &Receiver<ClientEvent>,
+                 mut app: App) -> io::Result<()> {
+    let mut dirty = true;
+    loop {
+        for event in events.try_iter() {
+            app.apply(event);
+            dirty = true;
+        }
+        if dirty {
+            app.draw(out)?;
+            dirty = false;
+        }
+        if !event::poll(Duration::from_millis(INPUT_POLL_IN_MILLIS))? {
+            continue;
+        }
+        match event::read()? {
+            //На некоторых системах приходят и нажатия и отпускания клавиш, нам нужны только нажатия
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                if !app.handle_key(key, client) {
+                    return Ok(());
+                }
+            }
+            Event::Resize(..) => {}
+            _ => continue,
+        }
+        dirty = true;
+    }
+}
+
+//Состояние подключения которое показывается в строке статуса
+enum Status {
+    //Ждем ответа сервера на просьбу пустить нас в чат
+    Connecting,
+    Connected,
+    //Сервер отказал во входе или завершил сессию
+    Rejected(String),
+}
+
+//Строка в истории сообщений
+struct Line {
+    text: String,
+    //Личные сообщения отображаются другим цветом
+    private: bool,
+    //Сообщения написанные до нашего входа в комнату отображаются тусклым цветом
+    history: bool,
+}
+
+//Все что отображается на экране
+struct App {
+    nickname: String,
+    status: Status,
+    //Как сервер отвечает на проверки связи
+    connection: ConnectionState,
+    rtt: Option<Duration>,
+    //Сообщения всех комнат в порядке прихода
+    lines: Vec<Line>,
+    //На сколько строк пользователь прокрутил историю вверх от последнего сообщения
+    scroll: usize,
+    //Текст который пользователь сейчас набирает
+    input: String,
+    //Комнаты в которых мы состоим
+    rooms: Vec<String>,
+    //Комната в которую уходят сообщения
+    current_room: String,
+}
+
+impl App {
+    fn new(nickname: &str) -> App {
+        App {
+            nickname: nickname.to_string(),
+            status: Status::Connecting,
+            connection: ConnectionState::Connected,
+            rtt: None,
+            lines: Vec::new(),
+            scroll: 0,
+            input: String::new(),
+            rooms: Vec::new(),
+            current_room: protocol::DEFAULT_ROOM.to_string(),
+        }
+    }
+
+    //Изменяет состояние в соответствии с пришедшим от сервера событием
+    fn apply(&mut self, event: ClientEvent) {
+        match event {
+            ClientEvent::Accepted { .. } => self.status = Status::Connected,
+            ClientEvent::Rejected { reason } => {
+                self.push(format!("* rejected: {}", reason), false);
+                self.status = Status::Rejected(reason);
+            }
+            //После входа в новую комнату сразу переключаемся на нее.
+            //Если мы вышли из текущей комнаты то переключаемся на первую из оставшихся
+            ClientEvent::Membership { rooms } => {
+                if let Some(joined) = rooms.iter().find(|room| !self.rooms.contains(room)) {
+                    self.current_room = joined.clone();
+                } else if !rooms.contains(&self.current_room) {
+                    self.current_room = rooms.first().cloned().unwrap_or_default();
+                }
+                self.rooms = rooms;
+            }
+            ClientEvent::Message { room, nickname, text } => self.push(format!("[#{}] {}: {}", room, nickname, text), false),
+            ClientEvent::History { room, nickname, text, .. } => {
+                self.lines.push(Line { text: format!("[#{}] {}: {}", room, nickname, text), private: false, history: true });
+            }
+            ClientEvent::Private { from, to, text } => self.push(format!("[private] {} -> {}: {}", from, to, text), true),
+            ClientEvent::Notice { ref room, ref text } if room.is_empty() => self.push(format!("* {}", text), false),
+            ClientEvent::Notice { room, text } => self.push(format!("[#{}] * {}", room, text), false),
+            ClientEvent::RoomList { rooms } => self.push(format!("* rooms: {}", rooms.join(", ")), false),
+            ClientEvent::Connection { state, rtt } => {
+                //Об обрыве связи и ее восстановлении пишем в историю, чтобы было видно где мог случиться пропуск
+                if state == ConnectionState::Reconnecting && self.connection != state {
+                    self.push("* connection lost, reconnecting...".to_string(), false);
+                } else if self.connection == ConnectionState::Reconnecting && state != ConnectionState::Reconnecting {
+                    self.push("* reconnected".to_string(), false);
+                }
+                self.connection = state;
+                self.rtt = rtt;
+            }
+            ClientEvent::Error { message } => self.push(format!("* error: {}", message), false),
+            //Отброшенные датаграммы обычны после переподключения, пользователю о них знать не нужно
+            ClientEvent::Dropped { .. } => {}
+        }
+    }
+
+    fn push(&mut self, text: String, private: bool) {
+        self.lines.push(Line { text, private, history: false });
+    }
+
+    //Обрабатывает нажатие клавиши. Возвращает false если пользователь хочет выйти
+    fn handle_key(&mut self, key: KeyEvent, client: &Mutex<ChatClient>) -> bool {
+        match key.code {
+            KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => self.submit(client),
+            KeyCode::Tab => self.next_room(),
+            //Дальше первой строки прокрутка не уйдет, ее ограничивает draw
+            KeyCode::PageUp => self.scroll += SCROLL_STEP,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(SCROLL_STEP),
+            _ => {}
+        }
+        true
+    }
+
+    //Отправляет набранную строку: команду или сообщение в текущую комнату
+    fn submit(&mut self, client: &Mutex<ChatClient>) {
+        let line = std::mem::take(&mut self.input);
+        if line.trim().is_empty() {
+            return;
+        }
+        let sent = match client.lock() {
+            Ok(mut client) => client.send_line(&line, &self.current_room),
+            Err(_) => return,
+        };
+        //Неотправленную строку возвращаем в поле ввода, чтобы ее не пришлось набирать заново
+        if let Err(e) = sent {
+            self.push(format!("* can't send: {}", e), false);
+            self.input = line;
+        }
+        //После отправки показываем самые новые сообщения
+        self.scroll = 0;
+    }
+
+    //Переключается на следующую комнату по кругу
+    fn next_room(&mut self) {
+        if self.rooms.is_empty() {
+            return;
+        }
+        let next = self.rooms
+            .iter()
+            .position(|room| *room == self.current_room)
+            .map(|index| (index + 1) % self.rooms.len())
+            .unwrap_or(0);
+        self.current_room = self.rooms[next].clone();
+    }
+
+    //Строки экрана с историей сообщений и их цвет. Многострочные сообщения разбиваем по строкам,
+    // а длинные строки переносим, чтобы переводы строк в тексте не ломали экран
+    fn rows(&self, width: usize) -> Vec<(String, Option<Color>)> {
+        self.lines
+            .iter()
+            .flat_map(|line| {
+                let color = if line.private {
+                    Some(Color::Magenta)
+                } else if line.history {
+                    Some(Color::DarkGrey)
+                } else {
+                    None
+                };
+                wrap(&line.text, width).into_iter().map(move |row| (row, color))
+            })
+            .collect()
+    }
+
+    //Рисует историю сообщений, строку статуса и строку ввода
+    fn draw<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let width = width.max(1) as usize;
+        //Две нижние строки экрана занимают статус и ввод
+        let pane = height.saturating_sub(2) as usize;
+        let rows = self.rows(width);
+        //Прокрутить можно только до самой первой строки экрана. Строк на экране становится больше
+        // и когда сообщения переносятся, и когда окно становится уже, поэтому считаем их при каждой отрисовке
+        self.scroll = self.scroll.min(rows.len().saturating_sub(pane));
+        let end = rows.len() - self.scroll;
+        let start = end.saturating_sub(pane);
+        queue!(out, terminal::Clear(ClearType::All))?;
+        for (index, &(ref row, color)) in rows[start..end].iter().enumerate() {
+            queue!(out, cursor::MoveTo(0, index as u16))?;
+            if let Some(color) = color {
+                queue!(out, SetForegroundColor(color))?;
+            }
+            queue!(out, Print(row), ResetColor)?;
+        }
+        let status = match self.status {
+            Status::Connecting => "connecting...".to_string(),
+            Status::Connected => {
+                let rtt = self.rtt.map(|rtt| format!(", rtt {} ms", rtt.as_millis())).unwrap_or_default();
+                match self.connection {
+                    ConnectionState::Connected => format!("connected as {}{}", self.nickname, rtt),
+                    ConnectionState::Degraded => format!("connection is poor{}", rtt),
+                    ConnectionState::Disconnected => "server doesn't respond".to_string(),
+                    ConnectionState::Reconnecting => "reconnecting...".to_string(),
+                }
+            }
+            Status::Rejected(ref reason) => format!("disconnected: {}", reason),
+        };
+        let status = format!(" {} | #{} | rooms: {} | Tab next room, PgUp/PgDn scroll, Esc quit",
+                             status, self.current_room, self.rooms.join(", "));
+        //Плохую связь подсвечиваем чтобы ее было видно сразу
+        let color = match (&self.status, self.connection) {
+            (Status::Connected, ConnectionState::Degraded) => Color::Yellow,
+            (Status::Connected, ConnectionState::Disconnected) | (_, ConnectionState::Reconnecting) => Color::Red,
+            _ => Color::Reset,
+        };
+        queue!(out,
+               cursor::MoveTo(0, height.saturating_sub(2)),
+               SetForegroundColor(color),
+               SetAttribute(Attribute::Reverse),
+               Print(format!("{:width$}", status.chars().take(width).collect::<String>(), width = width)),
+               SetAttribute(Attribute::Reset),
+               ResetColor)?;
+        //Если строка ввода не помещается на экран то показываем ее конец
+        let prompt = format!("> {}", self.input);
+        let skip = prompt.chars().count().saturating_sub(width.saturating_sub(1));
+        let prompt: String = prompt.chars().skip(skip).collect();
+        queue!(out,
+               cursor::MoveTo(0, height.saturating_sub(1)),
+               Print(&prompt),
+               cursor::Show)?;
+        out.flush()
+    }
+}
+
+//Разбивает текст на строки экрана не длиннее width символов.
+//Сначала по переводам строк, затем каждую строку на куски. Табуляции и прочие управляющие символы
+// заменяются пробелами, иначе в сыром режиме терминала они двигали бы курсор
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut rows = Vec::new();
+    for line in text.lines() {
+        let chars: Vec<char> = line.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+        if chars.is_empty() {
+            rows.push(String::new());
+        }
+        rows.extend(chars.chunks(width).map(|chunk| chunk.iter().collect::<String>()));
+    }
+    if rows.is_empty() {
+        rows.push(String::new());
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_on_newlines_and_width() {
+        assert_eq!(wrap("", 4), [""]);
+        assert_eq!(wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("fn main() {\n\n    x\r\n}", 8), ["fn main(", ") {", "", "    x", "}"]);
+        assert_eq!(wrap("a\tb", 8), ["a b"]);
+    }
+}
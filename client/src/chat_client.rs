@@ -0,0 +1,601 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::backoff::Backoff;
+use crate::heartbeat::{ConnectionState, Heartbeat};
+use protocol::{Datagram, Fingerprint, Kind, Packet, Reassembler, Reliable, Reorder, Secure};
+
+//Таймату в милисекундах после которого будет прервана блокирующая операция чтения из сокета.
+//Так же с этим интервалом проверяется нет ли неподтвержденных сервером пакетов которые пора отправить повторно
+const TIMEOUT_IN_MILLIS: u64 = 100;
+//Сколько миллисекунд disconnect ждет подтверждения выхода
+const LEAVE_TIMEOUT_IN_MILLIS: u64 = 1000;
+//Сколько пакетов пользователя держать пока мы переподключаемся к серверу
+const MAX_QUEUED: usize = 100;
+
+//Событие чата пришедшее от сервера
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    //Сервер пустил нас в чат и назначил нам идентификатор
+    Accepted { id: u32 },
+    //Сервер отказал во входе или завершил нашу сессию. После этого клиентом пользоваться нельзя
+    Rejected { reason: String },
+    //Изменился список комнат в которых мы состоим
+    Membership { rooms: Vec<String> },
+    //Сообщение в комнате от участника с ником nickname
+    Message { room: String, nickname: String, text: String },
+    //Сообщение которое написали в комнату до того как мы в нее вошли.
+    //timestamp это время сообщения в миллисекундах от начала эпохи UNIX
+    History { room: String, nickname: String, text: String, timestamp: u64 },
+    //Личное сообщение. Приходит и получателю и автору
+    Private { from: String, to: String, text: String },
+    //Служебное оповещение сервера. У оповещений не относящихся к какой либо комнате название комнаты пустое
+    Notice { room: String, text: String },
+    //Ответ на запрос списка комнат
+    RoomList { rooms: Vec<String> },
+    //Изменилось состояние связи с сервером или задержка ответа на проверку связи
+    Connection { state: ConnectionState, rtt: Option<Duration> },
+    //Не удалось отправить пакет серверу. Клиентом можно пользоваться дальше,
+    // а пакет требующий подтверждения будет отправлен повторно
+    Error { message: String },
+    //Пришла датаграмма которую не удалось расшифровать или разобрать, например устаревшая датаграмма
+    // прошлой сессии после переподключения. Датаграмма отброшена
+    Dropped { reason: String },
+}
+
+//Клиент чата без графического интерфейса. Через него работает окно приложения,
+// его же можно использовать в ботах и тестах
+#[derive(Debug)]
+pub struct ChatClient {
+    //Сокет подключенный к серверу. Все датаграммы шифруются ключами о которых мы договорились с сервером
+    socket: Secure<UdpSocket>,
+    server: SocketAddr,
+    //Номер последнего отправленного нами пакета
+    sequence: u32,
+    //Подтверждения, повторная отправка и отбрасывание дубликатов пакетов
+    reliable: Reliable,
+    //Восстанавливает порядок сообщений если они пришли не в том порядке в котором их отправил сервер
+    reorder: Reorder,
+    //Собирает большие сообщения из фрагментов
+    reassembler: Reassembler,
+    //Проверка того что сервер жив
+    heartbeat: Heartbeat,
+    //Ник, пароль и отпечаток сервера с которыми мы вошли в чат. С ними же входим заново после обрыва связи
+    nickname: String,
+    password: String,
+    pin: Option<Fingerprint>,
+    //Токен который сервер выдал при входе. По нему сервер восстанавливает нашу сессию
+    token: Option<String>,
+    //Комнаты в которых мы состоим
+    rooms: Vec<String>,
+    //Попытки переподключиться к серверу. None пока связь с ним есть
+    reconnect: Option<Backoff>,
+    //Мы вошли заново после обрыва связи и ждем ответа сервера. До ответа не принимаем
+    // пакеты старой сессии, ведь у новой сессии нумерация начнется сначала
+    rejoining: bool,
+    //Комнаты в которых мы были до обрыва связи. Если сервер забыл нашу сессию то возвращаемся в них сами
+    lost_rooms: Vec<String>,
+    //Ошибки которые случились во время обработки датаграммы. process возвращает их вместе с остальными событиями
+    errors: Vec<ClientEvent>,
+    //Сервер отказал нам. Связь больше не проверяем и не переподключаемся, иначе снова входили бы
+    // с тем же неверным паролем пока сервер не забанит наш адрес
+    rejected: bool,
+    //Пакеты пользователя которые сервер еще не подтвердил, по номеру пакета.
+    //Если связь оборвется то после переподключения они будут отправлены заново
+    unacked: Vec<(u32, Kind, Vec<u8>)>,
+    //Пакеты пользователя которые ждут окончания переподключения
+    queued: VecDeque<(Kind, Vec<u8>)>,
+}
+
+impl ChatClient {
+    //Подключается к серверу, договаривается с ним о ключах шифрования и просит пустить нас в чат под ником nickname.
+    //local это порт или полный адрес на котором мы слушаем ответы сервера.
+    //password это пароль пользователя или общий токен сервера, пустой если сервер пускает всех.
+    //Ответ сервера придет событием Accepted или Rejected
+    pub fn connect(local: &str, server_address: &str, nickname: &str, password: &str) -> io::Result<ChatClient> {
+        ChatClient::connect_pinned(local, server_address, nickname, password, None)
+    }
+
+    //То же что connect, но если указан pin то подключается только к серверу ключ которого имеет этот отпечаток.
+    //Отпечаток сервер пишет в лог при запуске
+    pub fn connect_pinned(local: &str,
+                          server_address: &str,
+                          nickname: &str,
+                          password: &str,
+                          pin: Option<Fingerprint>) -> io::Result<ChatClient> {
+        let servers = resolve_server(server_address)?;
+        ChatClient::connect_any(local, &servers, nickname, password, pin)
+    }
+
+    //Подключается к первому из адресов servers который ответил на обмен ключами.
+    //Адреса пробуются по порядку, поэтому их стоит передавать в том порядке в котором их вернул resolve_server
+    pub fn connect_any(local: &str,
+                       servers: &[SocketAddr],
+                       nickname: &str,
+                       password: &str,
+                       pin: Option<Fingerprint>) -> io::Result<ChatClient> {
+        let mut errors = Vec::new();
+        for &server in servers {
+            let connected = local_address(local, server)
+                .and_then(|local| ChatClient::connect_addr(local, server, nickname, password, pin));
+            match connected {
+                Ok(client) => return Ok(client),
+                Err(e) => errors.push((server, e)),
+            }
+        }
+        //Если адрес один то его ошибка понятна и без адреса
+        if errors.len() == 1 {
+            return Err(errors.remove(0).1);
+        }
+        let kind = errors.last().map(|(_, e)| e.kind()).unwrap_or(io::ErrorKind::NotFound);
+        let reasons: Vec<String> = errors.iter().map(|(server, e)| format!("{}: {}", server, e)).collect();
+        Err(io::Error::new(kind, reasons.join("; ")))
+    }
+
+    //То же что connect_pinned, но с уже разобранными адресами.
+    //Их можно заранее проверить через resolve_server и local_address
+    pub fn connect_addr(local: SocketAddr,
+                        server: SocketAddr,
+                        nickname: &str,
+                        password: &str,
+                        pin: Option<Fingerprint>) -> io::Result<ChatClient> {
+        let socket = create_socket(local, server)?;
+        let socket = Secure::connect(socket, server, pin)?;
+        let mut client = ChatClient {
+            socket,
+            server,
+            sequence: 0,
+            reliable: Reliable::new(),
+            reorder: Reorder::new(),
+            reassembler: Reassembler::new(),
+            heartbeat: Heartbeat::new(),
+            nickname: nickname.trim().to_string(),
+            password: password.to_string(),
+            pin,
+            token: None,
+            rooms: Vec::new(),
+            reconnect: None,
+            rejoining: false,
+            lost_rooms: Vec::new(),
+            errors: Vec::new(),
+            rejected: false,
+            unacked: Vec::new(),
+            queued: VecDeque::new(),
+        };
+        client.join()?;
+        Ok(client)
+    }
+
+    //Адрес сервера к которому мы подключены
+    pub fn server_addr(&self) -> SocketAddr {
+        self.server
+    }
+
+    //Отпечаток ключа сервера к которому мы подключены. Его можно сверить с тем что показывает сервер
+    // и указать в connect_pinned при следующих подключениях
+    pub fn server_fingerprint(&self) -> Option<Fingerprint> {
+        self.socket.fingerprint(self.server)
+    }
+
+    //Состояние связи с сервером по результатам последних проверок
+    pub fn connection_state(&self) -> ConnectionState {
+        if self.rejected {
+            ConnectionState::Disconnected
+        } else if self.reconnect.is_some() {
+            ConnectionState::Reconnecting
+        } else {
+            self.heartbeat.state()
+        }
+    }
+
+    //Сглаженная задержка ответа сервера. None пока не пришел ни один ответ
+    pub fn rtt(&self) -> Option<Duration> {
+        self.heartbeat.rtt()
+    }
+
+    //Отправляет сообщение в комнату
+    pub fn send_message(&mut self, room: &str, text: &str) -> io::Result<()> {
+        self.send(Kind::Message, protocol::encode_fields(&[room, text]))
+    }
+
+    //Отправляет личное сообщение участнику с ником nickname
+    pub fn send_private(&mut self, nickname: &str, text: &str) -> io::Result<()> {
+        self.send(Kind::Private, protocol::encode_fields(&[nickname, text]))
+    }
+
+    //Входит в комнату. Если такой комнаты нет то сервер ее создаст
+    pub fn join_room(&mut self, room: &str) -> io::Result<()> {
+        self.send(Kind::RoomJoin, room.trim().as_bytes().to_vec())
+    }
+
+    pub fn leave_room(&mut self, room: &str) -> io::Result<()> {
+        self.send(Kind::RoomLeave, room.trim().as_bytes().to_vec())
+    }
+
+    //Запрашивает список всех комнат. Ответ придет событием RoomList
+    pub fn list_rooms(&mut self) -> io::Result<()> {
+        self.send(Kind::RoomList, Vec::new())
+    }
+
+    //Отправляет строку введенную пользователем. Строки начинающиеся с / это команды
+    // /join <комната>, /leave [комната], /rooms и /msg <ник> <текст>, остальное сообщения в комнату room
+    pub fn send_line(&mut self, line: &str, room: &str) -> io::Result<()> {
+        let mut words = line.trim().splitn(2, ' ');
+        let command = words.next().unwrap_or("");
+        let argument = words.next().unwrap_or("").trim();
+        match command {
+            "/join" => self.join_room(argument),
+            "/leave" if argument.is_empty() => self.leave_room(room),
+            "/leave" => self.leave_room(argument),
+            "/rooms" => self.list_rooms(),
+            "/msg" => {
+                let mut words = argument.splitn(2, ' ');
+                let nickname = words.next().unwrap_or("");
+                let text = words.next().unwrap_or("").trim();
+                self.send_private(nickname, text)
+            }
+            _ => self.send_message(room, line),
+        }
+    }
+
+    //Сообщает серверу что мы выходим из чата и ждет подтверждения не дольше секунды,
+    // повторяя сообщение если оно потерялось. Если подтверждение так и не пришло
+    // то сервер отключит нас сам по таймауту и оповестит остальных о таймауте, а не о выходе
+    pub fn disconnect(mut self) -> io::Result<()> {
+        self.send(Kind::Leave, Vec::new())?;
+        let leave = self.sequence;
+        let deadline = Instant::now() + Duration::from_millis(LEAVE_TIMEOUT_IN_MILLIS);
+        //Пока ждем, process сам повторяет неподтвержденный выход
+        while self.reliable.is_pending(self.server, leave) && self.reconnect.is_none() {
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "server didn't confirm the leave"));
+            }
+            self.receive();
+        }
+        if self.reconnect.is_some() {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "server doesn't respond"));
+        }
+        Ok(())
+    }
+
+    //Ждет датаграмму от сервера не дольше таймаута чтения и возвращает события которые она принесла
+    pub fn receive(&mut self) -> Vec<ClientEvent> {
+        let datagram = read_datagram(self.socket.inner());
+        self.process(datagram.as_deref())
+    }
+
+    //Копия сокета чтобы ждать датаграммы в другом потоке не блокируя клиент.
+    //Прочитанные через нее датаграммы передаются в process
+    pub fn reader(&self) -> io::Result<UdpSocket> {
+        self.socket.inner().try_clone()
+    }
+
+    //Обрабатывает датаграмму от сервера или None если за время таймаута ничего не пришло.
+    //Расшифровывает ее, подтверждает получение, восстанавливает порядок сообщений,
+    // повторно отправляет пакеты которые сервер не подтвердил и проверяет связь с сервером.
+    //Если связь пропала то переподключается к серверу и возвращается в чат.
+    //Ошибки не прерывают работу клиента, они возвращаются событиями Error и Dropped
+    pub fn process(&mut self, datagram: Option<&[u8]>) -> Vec<ClientEvent> {
+        let mut events = self.update(datagram);
+        events.append(&mut self.errors);
+        events
+    }
+
+    fn update(&mut self, datagram: Option<&[u8]>) -> Vec<ClientEvent> {
+        let mut packets = datagram
+            .and_then(|bytes| self.open(bytes))
+            .map(|packet| self.accept_packet(packet))
+            .unwrap_or_default();
+        if self.reconnect.is_some() {
+            return self.reconnect();
+        }
+        packets.extend(self.restore_order());
+        for packet in &packets {
+            self.remember(packet);
+        }
+        let mut events: Vec<ClientEvent> = packets.iter().filter_map(to_event).collect();
+        //После отказа клиент только отдает оставшиеся события. Чтобы войти снова нужно подключиться заново
+        if self.rejected {
+            return events;
+        }
+        self.reassembler.expire();
+        let unreachable = !self.reliable.retransmit(&self.socket).is_empty();
+        //О каждом ответе на проверку сообщаем чтобы показать новую задержку
+        let mut changed = false;
+        for id in packets.iter().filter(|packet| packet.kind == Kind::Pong).filter_map(Packet::ping_id) {
+            changed |= self.heartbeat.pong(id);
+        }
+        if let Some(id) = self.heartbeat.poll() {
+            //Проверки не нумеруются, иначе сервер ждал бы пропущенные номера пакетов
+            let sent = self.socket.send_to(&protocol::encode(&Packet::ping(id)), self.server);
+            self.report(sent);
+        }
+        changed |= self.heartbeat.update();
+        //Сервер так и не подтвердил наши пакеты или давно не отвечает на проверки.
+        //Скорее всего он перезапустился или пропала сеть, поэтому договариваемся с ним о ключах заново
+        if unreachable || self.heartbeat.state() == ConnectionState::Disconnected {
+            self.socket.forget(&self.server);
+            self.reconnect = Some(Backoff::new());
+            changed = true;
+        }
+        if changed {
+            events.push(ClientEvent::Connection { state: self.connection_state(), rtt: self.heartbeat.rtt() });
+        }
+        events
+    }
+
+    //Очередная попытка переподключиться к серверу. Когда сервер ответил на обмен ключами
+    // входим в чат с токеном прошлой сессии, чтобы вернуться под тем же ником в те же комнаты
+    fn reconnect(&mut self) -> Vec<ClientEvent> {
+        if !self.socket.is_connected(self.server) {
+            if self.reconnect.as_mut().is_some_and(Backoff::poll) {
+                let sent = self.socket.hello(self.server, self.pin);
+                self.report(sent);
+            }
+            return Vec::new();
+        }
+        self.reconnect = None;
+        //Сервер начнет нумерацию пакетов новой сессии сначала, поэтому все что относилось к старой забываем
+        self.sequence = 0;
+        self.reliable = Reliable::new();
+        self.reorder = Reorder::new();
+        self.reassembler = Reassembler::new();
+        self.heartbeat = Heartbeat::new();
+        self.rejoining = true;
+        self.lost_rooms = self.rooms.clone();
+        //Неподтвержденные пакеты старой сессии отправим раньше тех что пользователь написал пока не было связи
+        for (_, kind, payload) in std::mem::take(&mut self.unacked).into_iter().rev() {
+            self.queued.push_front((kind, payload));
+        }
+        let sent = self.join();
+        self.report(sent);
+        vec![ClientEvent::Connection { state: self.connection_state(), rtt: None }]
+    }
+
+    //Просит сервер пустить нас в чат. Если есть токен прошлой сессии то сервер восстановит ее
+    fn join(&mut self) -> io::Result<()> {
+        let mut fields = vec![self.nickname.as_str(), self.password.as_str()];
+        if let Some(ref token) = self.token {
+            fields.push(token);
+        }
+        let payload = protocol::encode_fields(&fields);
+        self.send(Kind::Join, payload)
+    }
+
+    //Запоминает то что понадобится чтобы вернуться в чат после обрыва связи
+    fn remember(&mut self, packet: &Packet) {
+        match packet.kind {
+            Kind::Accepted => self.token = packet.text().ok().filter(|token| !token.is_empty()),
+            Kind::Rejected => self.rejected = true,
+            Kind::Membership => {
+                let rooms = packet.fields().unwrap_or_default();
+                //Сервер не помнил нашу сессию и пустил нас только в комнату по умолчанию
+                for room in std::mem::take(&mut self.lost_rooms) {
+                    if !rooms.contains(&room) {
+                        let sent = self.join_room(&room);
+                        self.report(sent);
+                    }
+                }
+                self.rooms = rooms;
+                //Мы снова в своих комнатах, теперь можно отправить то что написали пока не было связи
+                while let Some((kind, payload)) = self.queued.pop_front() {
+                    let sent = self.send(kind, payload);
+                    self.report(sent);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    //Подтверждает получение пакета от сервера и возвращает пакеты
+    // в том порядке в котором их отправил сервер.
+    //Для подтверждений, фрагментов и дубликатов уже обработанных пакетов ничего не возвращает
+    fn accept_packet(&mut self, packet: Packet) -> Vec<Packet> {
+        //Большое сообщение приходит по частям. Ждем пока не придут все части
+        let packet = match self.reassembler.accept(self.server, packet) {
+            Ok(Some(packet)) => packet,
+            Ok(None) => return Vec::new(),
+            Err(e) => {
+                self.drop_datagram(e);
+                return Vec::new();
+            }
+        };
+        //Пока сервер не ответил на повторный вход пакеты старой сессии пропускаем не подтверждая.
+        //Если это пакет новой сессии то сервер повторит его
+        if self.rejoining && packet.kind.is_reliable() {
+            if packet.kind != Kind::Accepted {
+                return Vec::new();
+            }
+            self.rejoining = false;
+        }
+        if packet.kind == Kind::Ack {
+            self.unacked.retain(|&(sequence, _, _)| sequence != packet.sequence);
+        }
+        let fresh = self.reliable.receive(&self.socket, self.server, &packet);
+        let fresh = self.report(fresh).unwrap_or(false);
+        if !fresh {
+            return Vec::new();
+        }
+        //Порядок есть только у пакетов которые сервер нумерует
+        if packet.kind.is_reliable() {
+            self.reorder.push(packet)
+        } else {
+            vec![packet]
+        }
+    }
+
+    //Просит сервер повторить пропущенные сообщения. Если они так и не пришли
+    // то возвращает придержанные пакеты не дожидаясь пропущенных
+    fn restore_order(&mut self) -> Vec<Packet> {
+        let missing = self.reorder.missing();
+        if !missing.is_empty() {
+            let sent = self.socket.send_to(&protocol::encode(&Packet::resend(&missing)), self.server);
+            self.report(sent);
+        }
+        self.reorder.expire()
+    }
+
+    //Расшифровывает и разбирает датаграмму от сервера. Для обмена ключами и испорченных датаграмм ничего не возвращает
+    fn open(&mut self, bytes: &[u8]) -> Option<Packet> {
+        match self.socket.open(bytes, self.server) {
+            Ok(Some(bytes)) => protocol::decode(&bytes).map_err(|e| self.drop_datagram(e)).ok(),
+            Ok(None) => None,
+            Err(e) => {
+                self.drop_datagram(e);
+                None
+            }
+        }
+    }
+
+    //Запоминает почему датаграмма от сервера отброшена
+    fn drop_datagram<E: std::fmt::Display>(&mut self, reason: E) {
+        self.errors.push(ClientEvent::Dropped { reason: reason.to_string() });
+    }
+
+    //Запоминает ошибку отправки и возвращает результат без нее
+    fn report<T>(&mut self, result: io::Result<T>) -> Option<T> {
+        result.map_err(|e| self.errors.push(ClientEvent::Error { message: format!("can't send: {}", e) })).ok()
+    }
+
+    //Отправляем пакет в сокет
+    fn send(&mut self, kind: Kind, payload: Vec<u8>) -> io::Result<()> {
+        if self.rejected {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "rejected by the server"));
+        }
+        //Ключей сервера пока нет, а пакеты старой сессии после переподключения все равно будут забыты.
+        //Поэтому пакеты пользователя откладываем до тех пор пока мы не вернемся в чат
+        if self.reconnect.is_some() || (self.rejoining && kind != Kind::Join) {
+            if kind == Kind::Join || kind == Kind::Leave {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "reconnecting to the server"));
+            }
+            if self.queued.len() >= MAX_QUEUED {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "too many messages are waiting for the reconnect"));
+            }
+            self.queued.push_back((kind, payload));
+            return Ok(());
+        }
+        //Каждый отправленный пакет получает следующий порядковый номер
+        self.sequence = self.sequence.wrapping_add(1);
+        //Упаковываем данные в пакет и отправляем в сокет.
+        //Свой идентификатор клиент не знает, его подставит сервер.
+        //Пакет будет отправляться повторно пока сервер не подтвердит его получение
+        let packet = Packet::new(kind, protocol::SERVER_ID, self.sequence, payload);
+        let result = self.reliable.send(&self.socket, self.server, &packet);
+        //Слишком большой пакет не отправлен и не запомнен, поэтому его номер достанется следующему пакету.
+        //Иначе сервер навсегда запомнил бы пропуск в нумерации
+        if result.as_ref().is_err_and(|e| e.kind() == io::ErrorKind::InvalidInput) {
+            self.sequence = self.sequence.wrapping_sub(1);
+        } else if kind != Kind::Join && kind != Kind::Leave {
+            self.unacked.push((packet.sequence, packet.kind, packet.payload));
+        }
+        result
+    }
+}
+
+//Читает одну датаграмму из сокета. Возвращает None если за время таймаута ничего не пришло
+pub fn read_datagram(socket: &UdpSocket) -> Option<Vec<u8>> {
+    //Буффер для данных которые будем считывать из сокета.
+    let mut buf = [0u8; 4096];
+    //Блокирующий вызов. Здесь поток выполнения останавливаеться до тех пор пока
+    // не будут считанные данные или произойдет таймаут.
+    socket.recv(&mut buf)
+        .map(|count| buf[..count].to_vec())
+        .ok()
+}
+
+//Преобразует пакет от сервера в событие чата
+fn to_event(packet: &Packet) -> Option<ClientEvent> {
+    match packet.kind {
+        //Свой идентификатор сервер присылает в поле отправителя
+        Kind::Accepted => return Some(ClientEvent::Accepted { id: packet.sender }),
+        Kind::Rejected => return Some(ClientEvent::Rejected { reason: packet.text().unwrap_or_default() }),
+        _ => {}
+    }
+    let fields = packet.fields()?;
+    match (packet.kind, fields.as_slice()) {
+        //Сервер присылает комнату и ник автора вместе с текстом сообщения
+        (Kind::Message, [room, nickname, text]) => Some(ClientEvent::Message {
+            room: room.clone(),
+            nickname: nickname.clone(),
+            text: text.clone(),
+        }),
+        (Kind::History, [room, nickname, text, timestamp]) => Some(ClientEvent::History {
+            room: room.clone(),
+            nickname: nickname.clone(),
+            text: text.clone(),
+            timestamp: timestamp.parse().ok()?,
+        }),
+        (Kind::Private, [from, to, text]) => Some(ClientEvent::Private {
+            from: from.clone(),
+            to: to.clone(),
+            text: text.clone(),
+        }),
+        (Kind::Notice, [room, text]) => Some(ClientEvent::Notice { room: room.clone(), text: text.clone() }),
+        (Kind::Membership, _) => Some(ClientEvent::Membership { rooms: fields }),
+        (Kind::RoomList, _) => Some(ClientEvent::RoomList { rooms: fields }),
+        _ => None,
+    }
+}
+
+//Разбирает адрес сервера вида хост:порт. Хост может быть IPv4 или IPv6 адресом или именем,
+// например localhost:7777 или [::1]:7777.
+//Возвращает все адреса имени в том порядке в котором их вернул резолвер системы
+pub fn resolve_server(address: &str) -> io::Result<Vec<SocketAddr>> {
+    let address = address.trim();
+    if address.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "server address is empty"));
+    }
+    let mut addresses: Vec<SocketAddr> = Vec::new();
+    let resolved = address.to_socket_addrs()
+        .map_err(|e| match e.kind() {
+            //Без порта стандартная библиотека пишет только invalid socket address
+            io::ErrorKind::InvalidInput => io::Error::new(io::ErrorKind::InvalidInput,
+                                                          format!("expected host:port, for example 127.0.0.1:7777, got {}", address)),
+            _ => io::Error::new(e.kind(), format!("can't resolve {}: {}", address, e)),
+        })?;
+    //Резолвер может вернуть один адрес несколько раз, например для разных типов сокетов
+    for server in resolved {
+        if !addresses.contains(&server) {
+            addresses.push(server);
+        }
+    }
+    if addresses.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("can't resolve {}", address)));
+    }
+    if addresses.iter().any(|server| server.port() == 0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "server port can't be 0"));
+    }
+    Ok(addresses)
+}
+
+//Разбирает адрес на котором мы слушаем ответы сервера. Вместо полного адреса, например [::1]:0,
+// можно указать только порт, тогда слушаем все интерфейсы того же семейства адресов что и у сервера.
+//Порт 0 или пустая строка значит любой свободный порт
+pub fn local_address(local: &str, server: SocketAddr) -> io::Result<SocketAddr> {
+    let local = local.trim();
+    if let Ok(address) = local.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    let port = if local.is_empty() {
+        0
+    } else {
+        local.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput,
+                                                 format!("expected a port from 0 to 65535 or an address, got {}", local)))?
+    };
+    let any: IpAddr = if server.is_ipv6() { Ipv6Addr::UNSPECIFIED.into() } else { Ipv4Addr::UNSPECIFIED.into() };
+    Ok(SocketAddr::new(any, port))
+}
+
+//Создает сокет подключенный к серверу
+fn create_socket(local: SocketAddr, server: SocketAddr) -> io::Result<UdpSocket> {
+    //Создаем UDP сокет который считывает пакеты приходящие на локальный адресс.
+    let socket = protocol::bind(local).map_err(|e| io::Error::new(e.kind(), format!("can't listen on {}: {}", local, e)))?;
+    //Говорим нашему UDP сокету читать пакеты только от этого сервера
+    socket.connect(server)?;
+    //Устанавливаем таймаут для операции чтения из сокета.
+    //Если не установить таймаут то операция чтения из сокета будет ждать бесконечно.
+    socket.set_read_timeout(Some(Duration::from_millis(TIMEOUT_IN_MILLIS)))?;
+    Ok(socket)
+}
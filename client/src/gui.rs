@@ -0,0 +1,767 @@
+use std::collections::BTreeMap;
+use azul;
+use std::sync::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use azul::traits::*;
+use crate::chat_client::{self, ChatClient, ClientEvent};
+use crate::heartbeat::ConnectionState;
+use protocol::Fingerprint;
+use std::net::SocketAddr;
+
+// MODEL ---------------------------------------------------------------------------------------------------------------------------
+//Это позволит отображать нашут структуру в виде строки в шаблоне вида {:?} например println!("{:?}",model)
+#[derive(Debug)]
+//Наша модель данных
+//Для того чтобы ее можно было использовать в Azul она обязательно должна реальизовать трейт Layout
+struct ChatDataModel {
+    //Флаг для проверки того подключен ли пользователь к серверу или нет
+    logged_in: bool,
+    //Модель для отображения формы для отправки сообщений на сервер и сохранения полученных с сервера сообщений
+    messaging_model: MessagingDataModel,
+    //Модель для отображения формы для подключения к серверу
+    login_model: LoginDataModel,
+    //Как сервер отвечает на проверки связи. Показывается в строке статуса
+    connection: ConnectionState,
+    //Задержка ответа сервера. None пока не пришел ни один ответ
+    rtt: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct LoginDataModel {
+    //Порт который ввел пользователь. Мы будем его прослушивать нашим сокетом.
+    port_input: azul::widgets::text_input::TextInputState,
+    //Адрес сервера котовый ввел пользователь. Мы будем к нему подключаться
+    address_input: azul::widgets::text_input::TextInputState,
+    //Ник под которым пользователь хочет войти в чат
+    nickname_input: azul::widgets::text_input::TextInputState,
+    //Поле для пароля. Вместо введенных символов в нем показываются точки
+    password_input: azul::widgets::text_input::TextInputState,
+    //Пароль пользователя или общий токен сервера. Пустой если сервер пускает всех
+    password: String,
+    //Отпечаток ключа сервера которому мы доверяем. Если пусто то подойдет любой сервер
+    fingerprint_input: azul::widgets::text_input::TextInputState,
+    //Ошибки в полях формы. Показываются под полем в котором найдены
+    port_error: Option<String>,
+    address_error: Option<String>,
+    nickname_error: Option<String>,
+    fingerprint_error: Option<String>,
+    //Причина по которой не удалось подключиться или сервер отказал во входе
+    error: Option<String>,
+    //Порт или адрес на котором слушать, адреса сервера и отпечаток пока идет подключение к серверу.
+    //Обмен ключами может занять несколько секунд, поэтому подключение идет не в потоке интерфейса
+    connecting: Option<(String, Vec<SocketAddr>, Option<Fingerprint>)>,
+}
+
+//Символ которым в поле пароля заменяется каждый введенный символ
+const PASSWORD_MASK: char = '•';
+
+impl LoginDataModel {
+    //Забирает только что введенные в поле пароля символы в password и заменяет их точками.
+    //Поле ввода умеет только дописывать символы в конец и стирать последний символ,
+    // поэтому все что не точка это новые символы, а пропавшие точки это стертые символы
+    fn mask_password(&mut self) {
+        let kept = self.password_input.text.chars().take_while(|&c| c == PASSWORD_MASK).count();
+        let typed: String = self.password_input.text.chars().skip(kept).collect();
+        let mut password: String = self.password.chars().take(kept).collect();
+        password.push_str(&typed);
+        self.password_input.text = std::iter::repeat_n(PASSWORD_MASK, password.chars().count()).collect();
+        self.password = password;
+    }
+
+    //Проверяет введенные данные и запоминает ошибки рядом с полями в которых они найдены.
+    //Возвращает порт на котором слушать, адреса сервера и отпечаток если все поля заполнены верно
+    fn validate(&mut self) -> Option<(String, Vec<SocketAddr>, Option<Fingerprint>)> {
+        let server = chat_client::resolve_server(&self.address_input.text);
+        //Порт проверяем для первого адреса сервера или для IPv4 пока адрес неизвестен,
+        // от семейства адресов зависит только адрес по умолчанию
+        let any = server.as_ref().ok()
+            .and_then(|servers| servers.first().cloned())
+            .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 0)));
+        let local = chat_client::local_address(&self.port_input.text, any);
+        let nickname = self.nickname_input.text.trim();
+        self.nickname_error = if nickname.is_empty() {
+            Some("Nickname is empty".to_string())
+        } else if nickname.chars().any(char::is_whitespace) {
+            Some("Nickname can't contain spaces".to_string())
+        } else {
+            None
+        };
+        //Если пусто то подойдет любой сервер
+        let pin = match self.fingerprint_input.text.trim() {
+            "" => Ok(None),
+            text => text.parse().map(Some),
+        };
+        self.port_error = local.as_ref().err().map(ToString::to_string);
+        self.address_error = server.as_ref().err().map(ToString::to_string);
+        self.fingerprint_error = pin.as_ref().err().cloned();
+        match (local, server, pin) {
+            (Ok(_), Ok(servers), Ok(pin)) if self.nickname_error.is_none() => {
+                Some((self.port_input.text.clone(), servers, pin))
+            }
+            _ => None,
+        }
+    }
+}
+
+//Строка в истории сообщений комнаты
+#[derive(Debug)]
+struct ChatLine {
+    text: String,
+    //Личные сообщения отображаются другим цветом
+    private: bool,
+    //Сообщения написанные до нашего входа в комнату отображаются серым
+    history: bool,
+}
+
+#[derive(Debug)]
+struct MessagingDataModel {
+    //Сообщение пользователя. Мы его отправим на сервер
+    text_input_state: azul::widgets::text_input::TextInputState,
+    //Сообщения которые пришли с сервера. У каждой комнаты своя история сообщений
+    messages: BTreeMap<String, Vec<ChatLine>>,
+    //Комнаты в которых мы состоим
+    rooms: Vec<String>,
+    //Комната сообщения которой мы сейчас видим и в которую пишем
+    current_room: String,
+    //Клиент через который мы общаемся с сервером.
+    client: Option<ChatClient>,
+    //Номер текущей сессии. Увеличивается при каждом входе и выходе,
+    // чтобы задача чтения из сокета прошлой сессии завершилась и не трогала новую
+    session: u64,
+    //Флаг для проверки того, пришло ли нам новое сообщение от сервера
+    has_new_message: bool,
+}
+
+//VIEW -------------------------------------------------------------------------------------------------------------------------------
+
+//css стили для нашего DOM
+const CUSTOM_CSS: &str = "
+.row { height: 50px; }
+.orange {
+    background: linear-gradient(to bottom, #f69135, #f37335);
+    font-color: white;
+    border-bottom: 1px solid #8d8d8d;
+}
+.private { font-color: #8e44ad; }
+.history { font-color: #8d8d8d; }
+.status { background: #2ecc71; font-color: white; }
+.degraded { background: #f39c12; }
+.disconnected { background: #e74c3c; }
+.error { font-color: #e74c3c; }";
+
+
+//Трейт для элементов потомков корневого DataModel
+trait Layout<T> {
+    //Создает елемент DOM на основе данных потомка с типом Т родителя.
+    fn layout(&self, info: azul::prelude::WindowInfo<T>, root: &T) -> azul::prelude::Dom<T> where T: Sized + azul::prelude::Layout;
+}
+
+impl Layout<ChatDataModel> for LoginDataModel {
+    //Создает форму для ввода данных необходимых для подключения к серверу.
+    fn layout(&self, info: azul::prelude::WindowInfo<ChatDataModel>, root: &ChatDataModel) -> azul::prelude::Dom<ChatDataModel> {
+        //Создаем кнопку с текстовой надписью Login
+        let button = azul::widgets::button::Button::with_label("Login")
+            //Преобразуем ее в обьект DOM
+            .dom()
+            //Добавляем ей класс row
+            .with_class("row")
+            //Добавляем ей css класс orange
+            .with_class("orange")
+            //Добавляем обработчик события для нажатия на кнопку
+            .with_callback(
+                azul::prelude::On::MouseUp,
+                azul::prelude::Callback(LoginController::login_pressed));
+
+        //Создаем текстовую метку с тектом Enter port to listen и css классом row
+        let port_label = azul::widgets::label::Label::new("Enter port or address to listen:")
+            .dom()
+            .with_class("row");
+        //Создаем текстовое поле для ввода текста с текстом из свойства нашей модели и css классом row
+        let port = azul::widgets::text_input::TextInput::new()
+            //Привязываем текстовое поле к свойству нашей DataModel
+            // Это двухсторонняя привязка. Теперь редактирование TextInput автоматически изменяет
+            // текст в свойстве нашей модели и обратное тоже верно. Если мы изменим текст в нашей модели то измениться текст в TextInput
+            .bind(info.window, &self.port_input, root)
+            .dom(&self.port_input)
+            .with_class("row");
+
+        // Тоже что и для port_label
+        let address_label = azul::widgets::label::Label::new("Enter server address:")
+            .dom()
+            .with_class("row");
+
+        //то же что и для port. Двухсторонняя привязка
+        let address = azul::widgets::text_input::TextInput::new()
+            .bind(info.window, &self.address_input, root)
+            .dom(&self.address_input)
+            .with_class("row");
+
+        let nickname_label = azul::widgets::label::Label::new("Enter nickname:")
+            .dom()
+            .with_class("row");
+
+        let nickname = azul::widgets::text_input::TextInput::new()
+            .bind(info.window, &self.nickname_input, root)
+            .dom(&self.nickname_input)
+            .with_class("row");
+
+        let password_label = azul::widgets::label::Label::new("Enter password (if the server requires one):")
+            .dom()
+            .with_class("row");
+
+        //Поле само дописывает введенный символ, а наш обработчик сразу прячет его за точкой
+        let password = azul::widgets::text_input::TextInput::new()
+            .bind(info.window, &self.password_input, root)
+            .dom(&self.password_input)
+            .with_class("row")
+            .with_callback(azul::prelude::On::TextInput, azul::prelude::Callback(LoginController::password_typed))
+            .with_callback(azul::prelude::On::VirtualKeyDown, azul::prelude::Callback(LoginController::password_typed));
+
+        let fingerprint_label = azul::widgets::label::Label::new("Enter server fingerprint (optional):")
+            .dom()
+            .with_class("row");
+
+        let fingerprint = azul::widgets::text_input::TextInput::new()
+            .bind(info.window, &self.fingerprint_input, root)
+            .dom(&self.fingerprint_input)
+            .with_class("row");
+
+        //Создаем корневой DOM элемент в который помещяем наши UI элементы.
+        //Под полем с ошибкой показываем что с ним не так
+        let mut dom = azul::prelude::Dom::new(azul::prelude::NodeType::Div);
+        let fields = vec![
+            (port_label, port, &self.port_error),
+            (address_label, address, &self.address_error),
+            (nickname_label, nickname, &self.nickname_error),
+            (password_label, password, &None),
+            (fingerprint_label, fingerprint, &self.fingerprint_error),
+        ];
+        for (label, input, error) in fields {
+            dom.add_child(label);
+            dom.add_child(input);
+            if let Some(error) = error {
+                dom.add_child(error_label(error));
+            }
+        }
+        dom.add_child(button);
+        //Пока идет обмен ключами показываем к какому серверу мы подключаемся
+        if let Some((_, ref servers, _)) = self.connecting {
+            let servers: Vec<String> = servers.iter().map(ToString::to_string).collect();
+            dom.add_child(azul::widgets::label::Label::new(format!("Connecting to {}...", servers.join(", "))).dom().with_class("row"));
+        }
+        //Если не удалось подключиться или сервер отказал во входе то показываем почему
+        if let Some(ref error) = self.error {
+            dom.add_child(error_label(error));
+        }
+        dom
+    }
+}
+
+//Текст ошибки выделенный цветом
+fn error_label(error: &str) -> azul::prelude::Dom<ChatDataModel> {
+    azul::widgets::label::Label::new(error.to_string())
+        .dom()
+        .with_class("row")
+        .with_class("error")
+}
+
+impl Layout<ChatDataModel> for MessagingDataModel {
+    //Создает форму для отправки и чтения сообдений
+    fn layout(&self, info: azul::prelude::WindowInfo<ChatDataModel>, root: &ChatDataModel) -> azul::prelude::Dom<ChatDataModel> {
+        //Создаем кнопку с тектом Send css классами row, orange и обработчиком события при ее нажатии
+        let button = azul::widgets::button::Button::with_label("Send")
+            .dom()
+            .with_class("row")
+            .with_class("orange")
+            .with_callback(azul::prelude::On::MouseUp, azul::prelude::Callback(MessagingController::send_pressed));
+        //Создаем поле для ввода текста с двухсторонней привязкой с свойству модели self.messaging_model.text_input_state
+        // и css классом row
+        let text = azul::widgets::text_input::TextInput::new()
+            .bind(info.window, &self.text_input_state, root)
+            .dom(&self.text_input_state)
+            .with_class("row");
+        //Текущая комната и список всех комнат в которых мы состоим
+        let room = azul::widgets::label::Label::new(format!("Room: #{} (rooms: {})", self.current_room, self.rooms.join(", ")))
+            .dom()
+            .with_class("row");
+        //Кнопка для переключения на следующую комнату
+        let next_room = azul::widgets::button::Button::with_label("Next room")
+            .dom()
+            .with_class("row")
+            .with_class("orange")
+            .with_callback(azul::prelude::On::MouseUp, azul::prelude::Callback(MessagingController::next_room_pressed));
+        //Кнопка для выхода из чата и возврата к форме входа
+        let logout = azul::widgets::button::Button::with_label("Logout")
+            .dom()
+            .with_class("row")
+            .with_class("orange")
+            .with_callback(azul::prelude::On::MouseUp, azul::prelude::Callback(MessagingController::logout_pressed));
+        //Строка статуса с состоянием связи с сервером. Цвет строки зависит от состояния
+        let rtt = root.rtt.map(|rtt| format!(", RTT {} ms", rtt.as_millis())).unwrap_or_default();
+        let (status_text, status_class) = match root.connection {
+            ConnectionState::Connected => (format!("Connected{}", rtt), None),
+            ConnectionState::Degraded => (format!("Connection is poor{}", rtt), Some("degraded")),
+            ConnectionState::Disconnected => ("Server doesn't respond".to_string(), Some("disconnected")),
+            ConnectionState::Reconnecting => ("Reconnecting...".to_string(), Some("disconnected")),
+        };
+        let mut status = azul::widgets::label::Label::new(status_text)
+            .dom()
+            .with_class("row")
+            .with_class("status");
+        if let Some(class) = status_class {
+            status = status.with_class(class);
+        }
+        //Создаем корневой дом элемент и помещяем в него наши UI элементы
+        let mut dom = azul::prelude::Dom::new(azul::prelude::NodeType::Div)
+            .with_child(status)
+            .with_child(room)
+            .with_child(next_room)
+            .with_child(logout)
+            .with_child(text)
+            .with_child(button);
+        //Добавляем тестовые метки которые отображают сообщения которые были написаны в текущей комнате
+        for i in self.messages.get(&self.current_room).into_iter().flatten() {
+            let mut label = azul::widgets::label::Label::new(i.text.clone()).dom().with_class("row");
+            if i.private {
+                label = label.with_class("private");
+            }
+            if i.history {
+                label = label.with_class("history");
+            }
+            dom.add_child(label);
+        }
+        dom
+    }
+}
+
+impl azul::prelude::Layout for ChatDataModel {
+    //Метод который создает конечный DOM и вызваеться каждый раз кода нужно перерисовать интерфейс
+    fn layout(&self, info: azul::prelude::WindowInfo<Self>) -> azul::prelude::Dom<Self> {
+        //Если мы уже подключены к серверу то показываем форму для отправки и чтения сообщений
+        //иначе отображаем форму для подключения к серверу
+        if self.logged_in {
+            self.messaging_model.layout(info, self)
+        } else {
+            self.login_model.layout(info, self)
+        }
+    }
+}
+
+//Запускает цикл отрисовки GUI и обработки ввода пользователя
+pub fn run() {
+    //Создаем приложение со стартовыми данными
+    let app = azul::prelude::App::new(ChatDataModel {
+        logged_in: false,
+        messaging_model: MessagingDataModel {
+            text_input_state: azul::widgets::text_input::TextInputState::new(""),
+            messages: BTreeMap::new(),
+            rooms: Vec::new(),
+            current_room: protocol::DEFAULT_ROOM.to_string(),
+            client: None,
+            session: 0,
+            has_new_message: false,
+        },
+        login_model: LoginDataModel::default(),
+        connection: ConnectionState::Connected,
+        rtt: None,
+    }, azul::prelude::AppConfig::default());
+    //Стили используемые приложением по умолчанию
+    let mut style = azul::prelude::css::native();
+    //Добавляем к ним наши собственные стили
+    style.merge(azul::prelude::css::from_str(CUSTOM_CSS).unwrap());
+    //Создаем окно в котором будет отображать наше приложение
+    let window = azul::prelude::Window::new(azul::prelude::WindowCreateOptions::default(), style).unwrap();
+    //Запускаем приложение в этом окне
+    app.run(window).unwrap();
+}
+
+//CONTROLLER -------------------------------------------------------------------------------------------------------------------------------------------------
+struct MessagingController {}
+
+struct LoginController {}
+
+impl MessagingController {
+    //Метод отрабатывает когда пользователь
+    // хочет оправить новое сообщение на сервер.
+    fn send_pressed(app_state: &mut azul::prelude::AppState<ChatDataModel>, _event: azul::prelude::WindowEvent<ChatDataModel>) -> azul::prelude::UpdateScreen {
+        //Получаем во владение мутекс с нашей моделью данных.
+        // Это блокирует поток отрисовки интерфейса до тех пор пока мютекс не будет освобожден.
+        let mut data = app_state.data.lock().unwrap();
+        //Делаем копию введенного пользователем текста
+        let message = data.messaging_model.text_input_state.text.clone();
+        let model = &mut data.messaging_model;
+        //Строки начинающиеся с / это команды, остальное сообщения в текущую комнату.
+        //Запись данных в сокент не блокирующая т.е. поток выполнения продолжит свою работу.
+        //Пока нет связи клиент сам придержит сообщение и отправит его после переподключения
+        let sent = match model.client.as_mut() {
+            Some(client) => client.send_line(&message, &model.current_room),
+            None => return azul::prelude::UpdateScreen::DontRedraw,
+        };
+        match sent {
+            //Очищаем поле ввода.
+            Ok(()) => model.text_input_state.text = "".into(),
+            //Текст остается в поле ввода, чтобы его можно было отправить еще раз
+            Err(e) => model.messages
+                .entry(model.current_room.clone())
+                .or_default()
+                .push(ChatLine { text: format!("ERROR: can't send: {}", e), private: false, history: false }),
+        }
+        //Сообщаем фреймворку что после обработки этого события нужно перерисовать интерфейс.
+        azul::prelude::UpdateScreen::Redraw
+    }
+
+    //Метод отрабатывает когда пользователь хочет переключиться на следующую комнату
+    fn next_room_pressed(app_state: &mut azul::prelude::AppState<ChatDataModel>, _event: azul::prelude::WindowEvent<ChatDataModel>) -> azul::prelude::UpdateScreen {
+        let mut data = app_state.data.lock().unwrap();
+        let model = &mut data.messaging_model;
+        if model.rooms.is_empty() {
+            return azul::prelude::UpdateScreen::DontRedraw;
+        }
+        //Ищем текущую комнату в списке и берем следующую за ней по кругу
+        let next = model.rooms
+            .iter()
+            .position(|room| *room == model.current_room)
+            .map(|index| (index + 1) % model.rooms.len())
+            .unwrap_or(0);
+        model.current_room = model.rooms[next].clone();
+        azul::prelude::UpdateScreen::Redraw
+    }
+
+    //Метод отрабатывает когда пользователь хочет выйти из чата.
+    //Сообщаем серверу что уходим, закрываем сокет и возвращаемся к форме входа.
+    //Введенные в форму входа данные остаются, чтобы можно было сразу войти снова
+    fn logout_pressed(app_state: &mut azul::prelude::AppState<ChatDataModel>, _event: azul::prelude::WindowEvent<ChatDataModel>) -> azul::prelude::UpdateScreen {
+        let mut data = app_state.data.lock().unwrap();
+        //Задача чтения из сокета увидит что сессия сменилась и завершится
+        data.messaging_model.session += 1;
+        //Клиент до секунды ждет подтверждения выхода, поэтому прощается с сервером в отдельном потоке.
+        //Если выход не дошел то сервер сам отключит нас по таймауту. Пишем об этом под формой входа
+        if let Some(client) = data.messaging_model.client.take() {
+            let app_data = app_state.data.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = client.disconnect() {
+                    app_data.modify(|state| {
+                        if !state.logged_in && state.login_model.connecting.is_none() {
+                            state.login_model.error = Some(format!("Logged out, but the server wasn't told: {}", e));
+                            state.messaging_model.has_new_message = true;
+                        }
+                    });
+                }
+            });
+        }
+        data.logged_in = false;
+        data.login_model.error = None;
+        azul::prelude::UpdateScreen::Redraw
+    }
+}
+
+impl LoginController {
+    //Метод отрабатывает когда пользователь вводит или стирает символ в поле пароля
+    fn password_typed(app_state: &mut azul::prelude::AppState<ChatDataModel>, _event: azul::prelude::WindowEvent<ChatDataModel>) -> azul::prelude::UpdateScreen {
+        app_state.data.modify(|state| state.login_model.mask_password());
+        azul::prelude::UpdateScreen::Redraw
+    }
+
+    //Метод отрабатывает когда пользователь хочет подключиться к серверу
+    fn login_pressed(app_state: &mut azul::prelude::AppState<ChatDataModel>, _event: azul::prelude::WindowEvent<ChatDataModel>) -> azul::prelude::UpdateScreen {
+        let temp = app_state.data.clone();
+        //Получаем во владение мьютекс
+        let mut data = temp.lock().unwrap();
+        //Если мы уже подключены или подключаемся к серверу то прерываем выполнение метода сообщаем фреймворку
+        // что нет необходимости перерисовывать интерфейс.
+        if data.messaging_model.client.is_some() || data.login_model.connecting.is_some() {
+            return azul::prelude::UpdateScreen::DontRedraw;
+        }
+        //Пока в форме есть ошибки не подключаемся, а показываем их. Исправив их можно сразу нажать Login снова.
+        //Если пользователь указал отпечаток сервера то подключаемся только к серверу с таким ключом
+        data.login_model.error = None;
+        data.login_model.mask_password();
+        data.login_model.connecting = data.login_model.validate();
+        if data.login_model.connecting.is_none() {
+            return azul::prelude::UpdateScreen::Redraw;
+        }
+        //Подключение выполняется асинхронно в потоке из пула потоков фреймворка Azul, а форма пока показывает что мы подключаемся.
+        //После подключения эта же задача будет читать сообщения из сокета
+        app_state.add_task(TasksService::connect_async, &[]);
+        //Добавляем повторяющуюся задачу которая выполеться в основном потоке.
+        // Любые длительные вычисления в этом демоне блокирует обновление интерфейса
+        app_state.add_daemon(azul::prelude::Daemon::unique(azul::prelude::DaemonCallback(DaemonService::redraw_daemon)));
+        //Сообщаем фреймворку что после обработки этого события нужно перерисовать интерфейс
+        azul::prelude::UpdateScreen::Redraw
+    }
+}
+
+//Services -------------------------------------------------------------------------------------------------
+struct TasksService {}
+
+impl TasksService {
+    //Асинхронная операция выполняющаяся в пуле потоков фреймворка azul.
+    //Подключается к серверу и просит пустить нас в чат, а затем читает сообщения из сокета.
+    //Флаг logged_in установится когда сервер ответит согласием
+    fn connect_async(app_data: Arc<Mutex<ChatDataModel>>, arc: Arc<()>) {
+        //Копируем все нужное для подключения, чтобы не держать мьютекс пока идет обмен ключами
+        let (target, nickname, password) = {
+            let data = app_data.lock().unwrap();
+            let login = &data.login_model;
+            (login.connecting.clone(), login.nickname_input.text.clone(), login.password.clone())
+        };
+        let (local, servers, pin) = match target {
+            Some(target) => target,
+            None => return,
+        };
+        //Имя сервера может указывать на несколько адресов, например на IPv6 и IPv4. Пробуем их по порядку
+        let client = ChatClient::connect_any(&local, &servers, &nickname, &password, pin);
+        {
+            let mut data = app_data.lock().unwrap();
+            data.login_model.connecting = None;
+            //Демон перерисует форму с результатом подключения
+            data.messaging_model.has_new_message = true;
+            let client = match client {
+                Ok(client) => client,
+                Err(e) => {
+                    data.login_model.error = Some(e.to_string());
+                    return;
+                }
+            };
+            let fingerprint = client.server_fingerprint();
+            //Каждый вход в чат начинает новую сессию
+            data.messaging_model.client = Some(client);
+            data.messaging_model.session += 1;
+            data.messaging_model.messages.clear();
+            //Показываем отпечаток сервера чтобы пользователь мог сверить его и закрепить при следующем входе
+            if let Some(fingerprint) = fingerprint {
+                data.messaging_model.messages
+                    .entry(protocol::DEFAULT_ROOM.to_string())
+                    .or_default()
+                    .push(ChatLine { text: format!("* server fingerprint {}", fingerprint), private: false, history: false });
+            }
+            data.messaging_model.rooms.clear();
+            data.messaging_model.current_room = protocol::DEFAULT_ROOM.to_string();
+            data.connection = ConnectionState::Connected;
+            data.rtt = None;
+        }
+        //Мьютекс уже освобожден. Дальше эта же задача читает сообщения из сокета
+        TasksService::read_from_socket_async(app_data, arc);
+    }
+
+    //Асинхронная операция выполняющаяся в пуле потоков фреймворка azul
+    fn read_from_socket_async(app_data: Arc<Mutex<ChatDataModel>>, _: Arc<()>) {
+        let temp = app_data.clone();
+        //Лочим мьютекс и получаем копию сокета клиента из нашей модели данных и номер сессии которой он принадлежит
+        let (socket, session) = {
+            let mut data = temp.lock().unwrap();
+            let socket = match data.messaging_model.client.as_ref().map(ChatClient::reader) {
+                Some(Ok(socket)) => Some(socket),
+                //Без копии сокета ответы сервера читать нечем. Возвращаемся к форме входа и показываем причину
+                Some(Err(e)) => {
+                    data.messaging_model.client = None;
+                    data.logged_in = false;
+                    data.login_model.error = Some(format!("Can't read from the socket: {}", e));
+                    data.messaging_model.has_new_message = true;
+                    None
+                }
+                None => None,
+            };
+            (socket, data.messaging_model.session)
+        };
+        drop(temp);
+        let socket = match socket {
+            Some(socket) => socket,
+            None => return,
+        };
+        loop {
+            //Пытаемся прочитать данные из сокета.
+            //Если не сделать копию сокета и напрямую ждать тут пока прийдет сообщение из сокета
+            // который в мьютексе в нашей модели денных
+            // то весь интерфейс переснанет обновляться до тех пор пока мы не освободим мьютекс
+            let datagram = chat_client::read_datagram(&socket);
+            //Если нам прило какоте то сообшение то изменяем нашу модель данных
+            // modify делает то же что и .lock().unwrap() с передачей результата в лямбду
+            // и освобождением мьютекса после того как закончиться код лямбды
+            //Флаг того что сервер завершил нашу сессию и читать из сокета больше не нужно
+            let mut stopped = false;
+            app_data.modify(|state| {
+                //Пользователь вышел из чата или уже вошел заново. Эта задача больше не нужна
+                if state.messaging_model.session != session {
+                    stopped = true;
+                    return;
+                }
+                //Клиент подтверждает пакеты, восстанавливает их порядок
+                // и повторно отправляет сообщения которые сервер не подтвердил
+                let events = match state.messaging_model.client.as_mut() {
+                    Some(client) => client.process(datagram.as_deref()),
+                    None => Vec::new(),
+                };
+                for event in events {
+                    if !TasksService::apply_event(state, event) {
+                        stopped = true;
+                    }
+                }
+                if state.messaging_model.client.is_none() {
+                    stopped = true;
+                }
+            });
+            if stopped {
+                return;
+            }
+        }
+    }
+
+    //Изменяет модель данных в соответствии с пришедшим от сервера событием.
+    //Возвращает false если сервер отказал нам и сессия закончилась
+    fn apply_event(state: &mut ChatDataModel, event: ClientEvent) -> bool {
+        //Устанавливаем флаг на то что у нас новое сообдение и интерфейс надо перерисовать
+        state.messaging_model.has_new_message = true;
+        match event {
+            // Утанавливаем флаг на то что пользователь уже подключился к серверу
+            ClientEvent::Accepted { .. } => state.logged_in = true,
+            //Сервер не пустил нас в чат. Возвращаемся к форме входа и показываем причину
+            ClientEvent::Rejected { reason } => {
+                state.logged_in = false;
+                state.login_model.error = Some(reason);
+                state.messaging_model.client = None;
+                return false;
+            }
+            //Изменилось состояние связи с сервером. Строка статуса перерисуется вместе со всем интерфейсом
+            ClientEvent::Connection { state: connection, rtt } => {
+                //Об обрыве связи пишем в текущую комнату, чтобы было видно где мог случиться пропуск
+                if connection == ConnectionState::Reconnecting && state.connection != connection {
+                    let model = &mut state.messaging_model;
+                    model.messages
+                        .entry(model.current_room.clone())
+                        .or_default()
+                        .push(ChatLine { text: "Connection lost, reconnecting...".to_string(), private: false, history: false });
+                }
+                state.connection = connection;
+                state.rtt = rtt;
+            }
+            //Сервер сообщил в каких комнатах мы теперь состоим
+            ClientEvent::Membership { rooms } => {
+                let model = &mut state.messaging_model;
+                //После входа в новую комнату сразу переключаемся на нее.
+                //Если мы вышли из текущей комнаты то переключаемся на первую из оставшихся
+                if let Some(joined) = rooms.iter().find(|room| !model.rooms.contains(room)) {
+                    model.current_room = joined.clone();
+                } else if !rooms.contains(&model.current_room) {
+                    model.current_room = rooms.first().cloned().unwrap_or_default();
+                }
+                model.rooms = rooms;
+            }
+            //Добавляем сообщение в историю его комнаты.
+            //Оповещения не относящиеся к какой либо комнате показываем в текущей
+            event => if let Some((room, message)) = TasksService::format_event(event) {
+                let model = &mut state.messaging_model;
+                let room = if room.is_empty() { model.current_room.clone() } else { room };
+                model.messages.entry(room).or_default().push(message);
+            },
+        }
+        true
+    }
+
+    //Преобразует событие в строку для отображения в чате.
+    //Возвращает название комнаты к которой относится строка и саму строку.
+    //Для строк не относящихся к какой либо комнате название пустое
+    fn format_event(event: ClientEvent) -> Option<(String, ChatLine)> {
+        let line = |text: String, private: bool| ChatLine { text, private, history: false };
+        match event {
+            ClientEvent::Message { room, nickname, text } => Some((room, line(format!("FROM: {} MESSAGE: {}", nickname, text), false))),
+            ClientEvent::History { room, nickname, text, .. } => Some((room, ChatLine {
+                text: format!("FROM: {} MESSAGE: {}", nickname, text),
+                private: false,
+                history: true,
+            })),
+            ClientEvent::Private { from, to, text } => Some((String::new(), line(format!("PRIVATE FROM: {} TO: {} MESSAGE: {}", from, to, text), true))),
+            ClientEvent::Notice { room, text } => Some((room, line(text, false))),
+            //Ответ на команду /rooms
+            ClientEvent::RoomList { rooms } => Some((String::new(), line(format!("Rooms: {}", rooms.join(", ")), false))),
+            ClientEvent::Error { message } => Some((String::new(), line(format!("ERROR: {}", message), false))),
+            //Отброшенные датаграммы не показываем, они обычны после переподключения
+            _ => None,
+        }
+    }
+}
+
+struct DaemonService {}
+
+impl DaemonService {
+    //Повторяющаяся синхронная операция выполняющая в основном потоке
+    fn redraw_daemon(state: &mut ChatDataModel, _resources: &mut azul::prelude::AppResources) -> (azul::prelude::UpdateScreen, azul::prelude::TerminateDaemon) {
+        //Если у нас есть новое сообщение то сообщаем фреймворку что нужно перерисовать
+        //интерфейс с нуля и продолжить работу этого демона
+        //иначе не рисуем интерфейс с начала но все равно вызываем этот метод в следующем цикле.
+        if state.messaging_model.has_new_message {
+            state.messaging_model.has_new_message = false;
+            (azul::prelude::UpdateScreen::Redraw, azul::prelude::TerminateDaemon::Continue)
+        } else {
+            (azul::prelude::UpdateScreen::DontRedraw, azul::prelude::TerminateDaemon::Continue)
+        }
+    }
+}
+
+/*
+use azul::{
+    prelude::*,
+    widgets::{button::Button, label::Label},
+};
+use std::{
+    thread,
+    time::{Duration, Instant},
+    sync::{Arc, Mutex},
+};
+
+struct MyDataModel {
+    counter: usize,
+}
+
+impl Layout for MyDataModel {
+        fn layout(&self, _info: WindowInfo<Self>) -> Dom<Self> {
+            let label = Label::new(format!("{}", self.counter)).dom();
+            let button = Button::with_label("Update counter").dom()
+                .with_callback(On::MouseUp, Callback(update_counter));
+            let async_task_button = Button::with_label("Start async").dom()
+                .with_callback(On::MouseUp, Callback(start_connection));
+
+            Dom::new(NodeType::Div)
+                .with_child(label)
+                .with_child(button)
+                .with_child(async_task_button)
+    }
+}
+
+fn update_counter(app_state: &mut AppState<MyDataModel>, _event: WindowEvent<MyDataModel>) -> UpdateScreen {
+    app_state.data.modify(|state| state.counter += 1);
+    UpdateScreen::Redraw
+}
+
+// Problem - blocks UI :(
+fn start_connection(app_state: &mut AppState<MyDataModel>, _event: WindowEvent<MyDataModel>) -> UpdateScreen {
+    //Добавляем асинхроную задачу
+    app_state.add_task(start_async_task, &[]);
+    //Добавляем демон
+    app_state.add_daemon(Daemon::unique(DaemonCallback(start_daemon)));
+    UpdateScreen::Redraw
+}
+
+fn start_daemon(state: &mut MyDataModel, _resources: &mut AppResources) -> (UpdateScreen, TerminateDaemon) {
+    //Блокирует UI на десять секунд
+    thread::sleep(Duration::from_secs(10));
+        state.counter += 10000;
+        (UpdateScreen::Redraw, TerminateDaemon::Continue)
+}
+
+fn start_async_task(app_data: Arc<Mutex<MyDataModel>>, _: Arc<()>) {
+     // simulate slow load
+    app_data.modify(|state| {
+        //Блокирует UI на десять секунд
+        thread::sleep(Duration::from_secs(10));
+        state.counter += 10000;
+    });
+}
+
+pub fn run() {
+    let model = MyDataModel { counter:0 };
+    let app = App::new(model, AppConfig::default());
+    app.run(Window::new(WindowCreateOptions::default(), css::native()).unwrap()).unwrap();
+}
+*/
\ No newline at end of file
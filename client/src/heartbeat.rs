@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+//Как часто клиент проверяет что сервер жив
+const PING_INTERVAL_IN_MILLIS: u64 = 1000;
+//Если сервер не отвечает дольше этого или отвечает медленнее DEGRADED_RTT_IN_MILLIS то связь плохая
+const DEGRADED_AFTER_IN_MILLIS: u64 = 3000;
+const DEGRADED_RTT_IN_MILLIS: u64 = 500;
+//Если сервер не отвечает дольше этого то связи с ним нет
+const DISCONNECTED_AFTER_IN_MILLIS: u64 = 10_000;
+//Сколько последних проверок ждут ответа. Ответы на более старые уже не интересны
+const MAX_PENDING_PINGS: usize = 16;
+
+//Состояние связи с сервером
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    //Сервер отвечает быстро
+    Connected,
+    //Сервер отвечает медленно или пропустил несколько проверок
+    Degraded,
+    //Сервер давно не отвечает
+    Disconnected,
+    //Связь с сервером потеряна и клиент пытается заново договориться с ним о ключах и войти в чат
+    Reconnecting,
+}
+
+//Регулярная проверка связи с сервером. Клиент отправляет Ping, сервер отвечает Pong,
+// по времени ответа считается задержка, а по его отсутствию понятно что сервер пропал
+#[derive(Debug)]
+pub struct Heartbeat {
+    //Номер следующей проверки
+    next_id: u32,
+    //Проверки на которые еще не пришел ответ и время их отправки
+    pending: VecDeque<(u32, Instant)>,
+    last_ping: Option<Instant>,
+    last_pong: Instant,
+    //Сглаженное время от отправки проверки до ответа на нее
+    rtt: Option<Duration>,
+    state: ConnectionState,
+}
+
+impl Heartbeat {
+    pub fn new() -> Heartbeat {
+        Heartbeat {
+            next_id: 0,
+            pending: VecDeque::new(),
+            last_ping: None,
+            last_pong: Instant::now(),
+            rtt: None,
+            state: ConnectionState::Connected,
+        }
+    }
+
+    //Номер проверки которую пора отправить или None если еще рано
+    pub fn poll(&mut self) -> Option<u32> {
+        let now = Instant::now();
+        if self.last_ping.is_some_and(|last| now - last < Duration::from_millis(PING_INTERVAL_IN_MILLIS)) {
+            return None;
+        }
+        self.next_id = self.next_id.wrapping_add(1);
+        self.last_ping = Some(now);
+        if self.pending.len() >= MAX_PENDING_PINGS {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((self.next_id, now));
+        Some(self.next_id)
+    }
+
+    //Учитывает ответ сервера на проверку id. Возвращает false если такой проверки мы не ждали
+    pub fn pong(&mut self, id: u32) -> bool {
+        let sent = match self.pending.iter().position(|&(pending, _)| pending == id) {
+            Some(index) => self.pending[index].1,
+            None => return false,
+        };
+        //Ответы на более ранние проверки уже не придут или придут позже этого
+        self.pending.retain(|&(pending, _)| pending.wrapping_sub(id) as i32 > 0);
+        let sample = sent.elapsed();
+        //Как в TCP: новое измерение весит одну восьмую, чтобы одна задержка не скакала в строке статуса
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => (rtt * 7 + sample) / 8,
+            None => sample,
+        });
+        self.last_pong = Instant::now();
+        true
+    }
+
+    //Пересчитывает состояние связи. Возвращает true если оно изменилось
+    pub fn update(&mut self) -> bool {
+        let silence = self.last_pong.elapsed();
+        let state = if silence >= Duration::from_millis(DISCONNECTED_AFTER_IN_MILLIS) {
+            ConnectionState::Disconnected
+        } else if silence >= Duration::from_millis(DEGRADED_AFTER_IN_MILLIS)
+            || self.rtt.is_some_and(|rtt| rtt >= Duration::from_millis(DEGRADED_RTT_IN_MILLIS)) {
+            ConnectionState::Degraded
+        } else {
+            ConnectionState::Connected
+        };
+        let changed = state != self.state;
+        self.state = state;
+        changed
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+}
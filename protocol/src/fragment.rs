@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::{DecodeError, Kind, Packet, HEADER_SIZE, SERVER_ID};
+
+//Максимальный размер датаграммы которую мы отправляем в сокет.
+//Пакеты больше этого размера разбиваются на фрагменты чтобы не упираться в MTU сети
+pub const MAX_DATAGRAM_SIZE: usize = 1200;
+//Размер заголовка фрагмента внутри данных пакета: номер фрагмента (2) + количество фрагментов (2)
+const FRAGMENT_HEADER_SIZE: usize = 4;
+//Сколько байт исходного пакета помещается в один фрагмент
+const CHUNK_SIZE: usize = MAX_DATAGRAM_SIZE - HEADER_SIZE - FRAGMENT_HEADER_SIZE;
+//Максимальный размер пакета который можно собрать из фрагментов.
+//Ограничение не дает отправителю заставить нас выделить память под пакет любого размера
+pub const MAX_PACKET_SIZE: usize = 256 * 1024;
+//На сколько фрагментов может быть разбит пакет не больше MAX_PACKET_SIZE
+const MAX_FRAGMENTS: usize = MAX_PACKET_SIZE.div_ceil(CHUNK_SIZE);
+//Сколько пакетов от одного отправителя можно собирать одновременно.
+//Если придет фрагмент еще одного пакета то самый старый недособранный пакет выбрасывается,
+// его все равно отправят повторно если он был нужен
+const MAX_PARTIALS_PER_SENDER: usize = 16;
+//Сколько секунд ждать недостающие фрагменты прежде чем выбросить уже полученные
+const REASSEMBLY_TIMEOUT_IN_SECS: u64 = 10;
+
+//Разбивает закодированный пакет на датаграммы не больше MAX_DATAGRAM_SIZE.
+//Пакет который и так помещается в одну датаграмму возвращается как есть.
+//Все фрагменты одного пакета получают один и тот же номер id.
+//Пакет больше MAX_PACKET_SIZE получатель все равно не соберет, поэтому для него возвращается ошибка
+pub fn split(bytes: Vec<u8>, id: u32) -> io::Result<Vec<Vec<u8>>> {
+    if bytes.len() <= MAX_DATAGRAM_SIZE {
+        return Ok(vec![bytes]);
+    }
+    if bytes.len() > MAX_PACKET_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("packet of {} bytes is larger than {} bytes", bytes.len(), MAX_PACKET_SIZE)));
+    }
+    let chunks: Vec<&[u8]> = bytes.chunks(CHUNK_SIZE).collect();
+    let count = chunks.len() as u16;
+    Ok(chunks.iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut payload = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            payload.extend_from_slice(&(index as u16).to_be_bytes());
+            payload.extend_from_slice(&count.to_be_bytes());
+            payload.extend_from_slice(chunk);
+            crate::encode(&Packet::new(Kind::Fragment, SERVER_ID, id, payload))
+        })
+        .collect())
+}
+
+//Фрагменты одного пакета которые уже пришли
+#[derive(Debug)]
+struct Partial {
+    chunks: Vec<Option<Vec<u8>>>,
+    //Сколько байт пакета уже пришло
+    size: usize,
+    started_at: Instant,
+}
+
+//Собирает пакеты из фрагментов
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    //Недособранные пакеты по адресу отправителя и номеру пакета
+    partial: HashMap<(SocketAddr, u32), Partial>,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::default()
+    }
+
+    //Принимает пакет пришедший из сокета. Обычные пакеты возвращаются как есть,
+    // фрагменты запоминаются и когда пришли все фрагменты возвращается собранный из них пакет
+    pub fn accept(&mut self, address: SocketAddr, packet: Packet) -> Result<Option<Packet>, DecodeError> {
+        if packet.kind != Kind::Fragment {
+            return Ok(Some(packet));
+        }
+        if packet.payload.len() < FRAGMENT_HEADER_SIZE {
+            return Err(DecodeError::TooShort);
+        }
+        let index = u16::from_be_bytes([packet.payload[0], packet.payload[1]]) as usize;
+        let count = u16::from_be_bytes([packet.payload[2], packet.payload[3]]) as usize;
+        let chunk = &packet.payload[FRAGMENT_HEADER_SIZE..];
+        if index >= count || chunk.len() > CHUNK_SIZE {
+            return Err(DecodeError::LengthMismatch);
+        }
+        if count > MAX_FRAGMENTS {
+            return Err(DecodeError::TooLarge);
+        }
+        let key = (address, packet.sequence);
+        if !self.partial.contains_key(&key) {
+            self.make_room(address);
+        }
+        let partial = self.partial.entry(key).or_insert_with(|| Partial {
+            chunks: vec![None; count],
+            size: 0,
+            started_at: Instant::now(),
+        });
+        //Количество фрагментов не совпадает с тем что было в первом фрагменте
+        if partial.chunks.len() != count {
+            self.partial.remove(&key);
+            return Err(DecodeError::LengthMismatch);
+        }
+        if partial.chunks[index].is_none() {
+            partial.size += chunk.len();
+            partial.chunks[index] = Some(chunk.to_vec());
+        }
+        if partial.size > MAX_PACKET_SIZE {
+            self.partial.remove(&key);
+            return Err(DecodeError::TooLarge);
+        }
+        if partial.chunks.iter().any(|chunk| chunk.is_none()) {
+            return Ok(None);
+        }
+        let bytes: Vec<u8> = self.partial.remove(&key)
+            .map(|partial| partial.chunks.into_iter().flatten().flatten().collect())
+            .unwrap_or_default();
+        crate::decode(&bytes).map(Some)
+    }
+
+    //Выбрасывает самый старый недособранный пакет отправителя если он собирает слишком много пакетов сразу
+    fn make_room(&mut self, address: SocketAddr) {
+        let partials = self.partial.iter().filter(|&(&(a, _), _)| a == address);
+        if partials.clone().count() < MAX_PARTIALS_PER_SENDER {
+            return;
+        }
+        let oldest = partials.min_by_key(|&(_, partial)| partial.started_at).map(|(&key, _)| key);
+        if let Some(oldest) = oldest {
+            self.partial.remove(&oldest);
+        }
+    }
+
+    //Выбрасывает пакеты недостающие фрагменты которых так и не пришли
+    pub fn expire(&mut self) {
+        let timeout = Duration::from_secs(REASSEMBLY_TIMEOUT_IN_SECS);
+        self.partial.retain(|_, partial| partial.started_at.elapsed() < timeout);
+    }
+
+    //Забывает недособранные пакеты от отправителя, например когда клиент отключился
+    pub fn forget(&mut self, address: &SocketAddr) {
+        self.partial.retain(|&(a, _), _| a != *address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address() -> SocketAddr {
+        "127.0.0.1:7777".parse().unwrap()
+    }
+
+    fn fragment(sequence: u32, index: u16, count: u16, chunk: &[u8]) -> Packet {
+        let mut payload = index.to_be_bytes().to_vec();
+        payload.extend_from_slice(&count.to_be_bytes());
+        payload.extend_from_slice(chunk);
+        Packet::new(Kind::Fragment, SERVER_ID, sequence, payload)
+    }
+
+    #[test]
+    fn split_and_reassemble() {
+        let original = Packet::new(Kind::Message, 1, 1, vec![42; 5000]);
+        let datagrams = split(crate::encode(&original), 9).unwrap();
+        assert!(datagrams.len() > 1);
+        assert!(datagrams.iter().all(|datagram| datagram.len() <= MAX_DATAGRAM_SIZE));
+        let mut reassembler = Reassembler::new();
+        let mut packets = Vec::new();
+        //Фрагменты могут прийти в любом порядке и по несколько раз
+        for datagram in datagrams[1..].iter().rev().chain(&datagrams[1..2]).chain(&datagrams[..1]) {
+            let packet = crate::decode(datagram).unwrap();
+            packets.extend(reassembler.accept(address(), packet).unwrap());
+        }
+        assert_eq!(packets, vec![original]);
+    }
+
+    #[test]
+    fn split_rejects_oversized_packet() {
+        assert!(split(vec![0; MAX_PACKET_SIZE], 1).is_ok());
+        let error = split(vec![0; MAX_PACKET_SIZE + 1], 1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn rejects_too_many_fragments() {
+        let mut reassembler = Reassembler::new();
+        let packet = fragment(1, 0, u16::MAX, b"chunk");
+        assert_eq!(reassembler.accept(address(), packet), Err(DecodeError::TooLarge));
+        assert!(reassembler.partial.is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_fragment_headers() {
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.accept(address(), fragment(1, 2, 2, b"chunk")), Err(DecodeError::LengthMismatch));
+        assert_eq!(reassembler.accept(address(), fragment(1, 0, 2, &[0; CHUNK_SIZE + 1])), Err(DecodeError::LengthMismatch));
+        assert_eq!(reassembler.accept(address(), fragment(1, 0, 2, b"chunk")), Ok(None));
+        //Количество фрагментов не совпадает с первым фрагментом
+        assert_eq!(reassembler.accept(address(), fragment(1, 1, 3, b"chunk")), Err(DecodeError::LengthMismatch));
+        assert!(reassembler.partial.is_empty());
+    }
+
+    #[test]
+    fn limits_partials_per_sender() {
+        let mut reassembler = Reassembler::new();
+        let other: SocketAddr = "127.0.0.1:7778".parse().unwrap();
+        assert_eq!(reassembler.accept(other, fragment(1, 0, 2, b"chunk")), Ok(None));
+        for sequence in 0..MAX_PARTIALS_PER_SENDER as u32 * 2 {
+            assert_eq!(reassembler.accept(address(), fragment(sequence, 0, 2, b"chunk")), Ok(None));
+        }
+        let partials = reassembler.partial.keys().filter(|&&(a, _)| a == address()).count();
+        assert_eq!(partials, MAX_PARTIALS_PER_SENDER);
+        //Пакеты других отправителей не выбрасываются
+        assert!(reassembler.partial.contains_key(&(other, 1)));
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+
+//Размер открытого ключа и секрета Ed25519
+pub const IDENTITY_KEY_SIZE: usize = 32;
+//Размер подписи Ed25519
+pub const SIGNATURE_SIZE: usize = 64;
+
+//Постоянный ключ сервера, аналог самоподписанного сертификата.
+//Сервер подписывает им каждый обмен ключами, а клиент проверяет подпись и сверяет отпечаток ключа
+// с тем которому он доверяет. Так клиент знает что говорит с настоящим сервером, а не с тем кто встал посередине
+pub struct Identity {
+    key: SigningKey,
+}
+
+impl Identity {
+    //Новый случайный ключ
+    pub fn generate() -> Identity {
+        Identity { key: SigningKey::generate(&mut OsRng) }
+    }
+
+    //Читает ключ из файла. Если файла нет то создает новый ключ и сохраняет его,
+    // чтобы отпечаток сервера не менялся между запусками
+    pub fn load_or_generate(path: &Path) -> io::Result<Identity> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                let secret = parse_hex::<IDENTITY_KEY_SIZE>(&text)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "identity file is damaged"))?;
+                Ok(Identity { key: SigningKey::from_bytes(&secret) })
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                let identity = Identity::generate();
+                write_secret(path, &to_hex(&identity.key.to_bytes()))?;
+                Ok(identity)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    //Отпечаток ключа который сообщают клиентам чтобы они могли его закрепить
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.public_key())
+    }
+
+    pub(crate) fn public_key(&self) -> [u8; IDENTITY_KEY_SIZE] {
+        self.key.verifying_key().to_bytes()
+    }
+
+    pub(crate) fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_SIZE] {
+        self.key.sign(message).to_bytes()
+    }
+}
+
+impl fmt::Debug for Identity {
+    //Секрет в лог не попадает, только отпечаток
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Identity({})", self.fingerprint())
+    }
+}
+
+//Проверяет подпись сделанную ключом сервера
+pub(crate) fn verify(public_key: &[u8; IDENTITY_KEY_SIZE], message: &[u8], signature: &[u8; SIGNATURE_SIZE]) -> bool {
+    VerifyingKey::from_bytes(public_key)
+        .map(|key| key.verify(message, &Signature::from_bytes(signature)).is_ok())
+        .unwrap_or(false)
+}
+
+//SHA-256 от открытого ключа сервера. Записывается шестнадцатеричными цифрами
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub(crate) fn of(public_key: &[u8; IDENTITY_KEY_SIZE]) -> Fingerprint {
+        Fingerprint(Sha256::digest(public_key).into())
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_hex(&self.0))
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+//Разбирает отпечаток из шестнадцатеричной строки. Двоеточия между байтами допускаются
+impl FromStr for Fingerprint {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Fingerprint, String> {
+        parse_hex(&text.replace(':', ""))
+            .map(Fingerprint)
+            .ok_or_else(|| format!("fingerprint must be 64 hex digits, got {}", text.trim()))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let text = text.trim();
+    if text.len() != N * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+//Записывает секрет (ключ, хеши паролей) в файл который может читать только его владелец.
+//Уже существующий файл перезаписывается
+#[cfg(unix)]
+pub fn write_secret(path: &Path, text: &str) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    //mode действует только при создании файла, а у уже существующего права могли быть шире
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(text.as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn write_secret(path: &Path, text: &str) -> io::Result<()> {
+    fs::write(path, text)
+}
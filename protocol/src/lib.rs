@@ -0,0 +1,399 @@
+mod fragment;
+mod identity;
+mod reliable;
+mod reorder;
+mod secure;
+mod socket;
+
+pub use fragment::{Reassembler, MAX_DATAGRAM_SIZE, MAX_PACKET_SIZE};
+pub use identity::{write_secret, Fingerprint, Identity};
+pub use reliable::Reliable;
+pub use reorder::Reorder;
+pub use secure::{Sealer, Secure, SecureError, SEAL_OVERHEAD};
+pub use socket::{bind, Datagram, LossySocket};
+
+use std::error::Error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//Версия протокола. Увеличивается при любом несовместимом изменении формата пакета.
+//Во второй версии все пакеты передаются зашифрованными
+pub const VERSION: u8 = 2;
+//Идентификатор отправителя для сообщений которые создает сам сервер
+pub const SERVER_ID: u32 = 0;
+//Комната в которую клиент попадает сразу после входа в чат
+pub const DEFAULT_ROOM: &str = "general";
+//Размер заголовка пакета в байтах:
+// версия (1) + тип (1) + отправитель (4) + номер (4) + время (8) + длина данных (4)
+const HEADER_SIZE: usize = 22;
+
+//Тип пакета
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    //Сообщение чата
+    Message,
+    //Клиент отключается от сервера
+    Leave,
+    //Служебное оповещение от сервера, например о том что кто то отключился
+    Notice,
+    //Подтверждение получения пакета с номером sequence
+    Ack,
+    //Просьба повторить пакеты с номерами перечисленными в данных пакета
+    Resend,
+    //Часть пакета который не поместился в одну датаграмму. Номер пакета у всех его частей общий
+    Fragment,
+    //Клиент хочет войти в чат. В данных пакета ник и пароль, записанные через encode_fields.
+    //Если сервер пускает всех то пароль пустой. После обрыва связи третьим полем идет токен сессии,
+    // по нему сервер возвращает клиенту его ник и комнаты не проверяя пароль
+    Join,
+    //Сервер принял клиента. В поле sender идентификатор который сервер выдал клиенту,
+    // в данных пакета токен для восстановления сессии
+    Accepted,
+    //Сервер отказал клиенту. В данных пакета причина отказа
+    Rejected,
+    //Клиент хочет войти в комнату название которой в данных пакета
+    RoomJoin,
+    //Клиент хочет выйти из комнаты название которой в данных пакета
+    RoomLeave,
+    //Запрос списка комнат от клиента и ответ сервера с названиями всех комнат
+    RoomList,
+    //Названия комнат в которых состоит клиент. Сервер присылает их после каждого изменения
+    Membership,
+    //Личное сообщение. Клиент присылает ник получателя и текст,
+    // сервер пересылает получателю и автору ник автора, ник получателя и текст
+    Private,
+    //Сообщение из истории комнаты которое было написано до того как клиент в нее вошел.
+    //В данных пакета комната, ник автора, текст и время сообщения в миллисекундах от начала эпохи UNIX
+    History,
+    //Клиент проверяет что сервер жив. В данных пакета номер проверки (4 байта)
+    Ping,
+    //Ответ сервера на Ping с теми же данными. По времени ответа клиент измеряет задержку
+    Pong,
+}
+
+impl Kind {
+    fn to_byte(self) -> u8 {
+        match self {
+            Kind::Message => 1,
+            Kind::Leave => 2,
+            Kind::Notice => 3,
+            Kind::Ack => 4,
+            Kind::Resend => 5,
+            Kind::Fragment => 6,
+            Kind::Join => 7,
+            Kind::Accepted => 8,
+            Kind::Rejected => 9,
+            Kind::RoomJoin => 10,
+            Kind::RoomLeave => 11,
+            Kind::RoomList => 12,
+            Kind::Membership => 13,
+            Kind::Private => 14,
+            Kind::History => 15,
+            Kind::Ping => 16,
+            Kind::Pong => 17,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Kind> {
+        match byte {
+            1 => Some(Kind::Message),
+            2 => Some(Kind::Leave),
+            3 => Some(Kind::Notice),
+            4 => Some(Kind::Ack),
+            5 => Some(Kind::Resend),
+            6 => Some(Kind::Fragment),
+            7 => Some(Kind::Join),
+            8 => Some(Kind::Accepted),
+            9 => Some(Kind::Rejected),
+            10 => Some(Kind::RoomJoin),
+            11 => Some(Kind::RoomLeave),
+            12 => Some(Kind::RoomList),
+            13 => Some(Kind::Membership),
+            14 => Some(Kind::Private),
+            15 => Some(Kind::History),
+            16 => Some(Kind::Ping),
+            17 => Some(Kind::Pong),
+            _ => None,
+        }
+    }
+
+    //Нужно ли подтверждать получение пакетов этого типа
+    pub fn is_reliable(self) -> bool {
+        match self {
+            Kind::Message | Kind::Leave | Kind::Notice | Kind::Join | Kind::Accepted
+            | Kind::RoomJoin | Kind::RoomLeave | Kind::RoomList | Kind::Membership | Kind::Private
+            | Kind::History => true,
+            //Отказ отправляется клиенту у которого нет сессии, а значит и нумерации пакетов.
+            //Потерянную проверку связи повторять незачем, следующая уйдет и так
+            Kind::Ack | Kind::Resend | Kind::Fragment | Kind::Rejected | Kind::Ping | Kind::Pong => false,
+        }
+    }
+}
+
+//Пакет которым обмениваются клиент и сервер
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    //Тип пакета
+    pub kind: Kind,
+    //Идентификатор отправителя который ему выдал сервер
+    pub sender: u32,
+    //Порядковый номер пакета у отправителя
+    pub sequence: u32,
+    //Время создания пакета в миллисекундах от начала эпохи UNIX
+    pub timestamp: u64,
+    //Полезные данные пакета
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    //Создает пакет с текущим временем
+    pub fn new(kind: Kind, sender: u32, sequence: u32, payload: Vec<u8>) -> Packet {
+        Packet {
+            kind,
+            sender,
+            sequence,
+            timestamp: now_millis(),
+            payload,
+        }
+    }
+
+    //Создает просьбу повторить пакеты с перечисленными номерами
+    pub fn resend(sequences: &[u32]) -> Packet {
+        let payload = sequences.iter().flat_map(|s| s.to_be_bytes().to_vec()).collect();
+        Packet::new(Kind::Resend, SERVER_ID, 0, payload)
+    }
+
+    //Создает проверку связи с номером id
+    pub fn ping(id: u32) -> Packet {
+        Packet::new(Kind::Ping, SERVER_ID, 0, id.to_be_bytes().to_vec())
+    }
+
+    //Номер проверки связи из пакета Ping или Pong
+    pub fn ping_id(&self) -> Option<u32> {
+        if self.payload.len() == 4 {
+            Some(read_u32(&self.payload))
+        } else {
+            None
+        }
+    }
+
+    //Номера пакетов перечисленные в данных пакета Resend
+    pub fn sequences(&self) -> Vec<u32> {
+        self.payload.chunks_exact(4).map(read_u32).collect()
+    }
+
+    //Декодирует полезные данные пакета как UTF8 строку
+    pub fn text(&self) -> Result<String, std::string::FromUtf8Error> {
+        String::from_utf8(self.payload.clone())
+    }
+
+    //Декодирует полезные данные пакета как набор строк записанных через encode_fields
+    pub fn fields(&self) -> Option<Vec<String>> {
+        decode_fields(&self.payload)
+    }
+}
+
+//Записывает несколько строк в массив байт. Перед каждой строкой идет ее длина (4 байта).
+//Так сервер передает например ник автора сообщения вместе с текстом
+pub fn encode_fields(fields: &[&str]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for field in fields {
+        bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(field.as_bytes());
+    }
+    bytes
+}
+
+//Читает строки записанные через encode_fields. Возвращает None если данные повреждены
+pub fn decode_fields(mut bytes: &[u8]) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < 4 {
+            return None;
+        }
+        let length = read_u32(&bytes[..4]) as usize;
+        let field = bytes.get(4..4usize.checked_add(length)?)?;
+        fields.push(String::from_utf8(field.to_vec()).ok()?);
+        bytes = &bytes[4 + length..];
+    }
+    Some(fields)
+}
+
+//Ошибка разбора пакета из массива байт
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    //Данных меньше чем размер заголовка
+    TooShort,
+    //Пакет собран другой, несовместимой версией протокола
+    UnsupportedVersion(u8),
+    //Неизвестный тип пакета
+    UnknownKind(u8),
+    //Длина данных в заголовке не совпадает с реальной
+    LengthMismatch,
+    //Пакет собранный из фрагментов больше чем MAX_PACKET_SIZE
+    TooLarge,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::TooShort => write!(f, "packet is too short"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported protocol version {}", v),
+            DecodeError::UnknownKind(k) => write!(f, "unknown packet kind {}", k),
+            DecodeError::LengthMismatch => write!(f, "payload length mismatch"),
+            DecodeError::TooLarge => write!(f, "packet is larger than {} bytes", MAX_PACKET_SIZE),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+//Преобразует пакет в массив байт для отправки в сокет.
+//Все числа записываются в порядке байт big endian
+pub fn encode(packet: &Packet) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + packet.payload.len());
+    bytes.push(VERSION);
+    bytes.push(packet.kind.to_byte());
+    bytes.extend_from_slice(&packet.sender.to_be_bytes());
+    bytes.extend_from_slice(&packet.sequence.to_be_bytes());
+    bytes.extend_from_slice(&packet.timestamp.to_be_bytes());
+    bytes.extend_from_slice(&(packet.payload.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&packet.payload);
+    bytes
+}
+
+//Собирает пакет из массива байт полученных из сокета
+pub fn decode(bytes: &[u8]) -> Result<Packet, DecodeError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(DecodeError::TooShort);
+    }
+    if bytes[0] != VERSION {
+        return Err(DecodeError::UnsupportedVersion(bytes[0]));
+    }
+    let kind = Kind::from_byte(bytes[1]).ok_or(DecodeError::UnknownKind(bytes[1]))?;
+    let sender = read_u32(&bytes[2..6]);
+    let sequence = read_u32(&bytes[6..10]);
+    let timestamp = read_u64(&bytes[10..18]);
+    let length = read_u32(&bytes[18..22]) as usize;
+    let payload = &bytes[HEADER_SIZE..];
+    if payload.len() != length {
+        return Err(DecodeError::LengthMismatch);
+    }
+    Ok(Packet {
+        kind,
+        sender,
+        sequence,
+        timestamp,
+        payload: payload.to_vec(),
+    })
+}
+
+//Текущее время в миллисекундах от начала эпохи UNIX
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(bytes);
+    u32::from_be_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_be_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(kind: Kind, payload: &[u8]) -> Packet {
+        Packet {
+            kind,
+            sender: 42,
+            sequence: 0xdead_beef,
+            timestamp: 1_234_567_890_123,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn round_trip_every_kind() {
+        for byte in 1..=17 {
+            let kind = Kind::from_byte(byte).unwrap();
+            assert_eq!(kind.to_byte(), byte);
+            let original = packet(kind, b"hello");
+            assert_eq!(decode(&encode(&original)), Ok(original));
+        }
+    }
+
+    #[test]
+    fn round_trip_empty_and_binary_payload() {
+        let empty = packet(Kind::Ack, &[]);
+        assert_eq!(encode(&empty).len(), HEADER_SIZE);
+        assert_eq!(decode(&encode(&empty)), Ok(empty));
+        let binary: Vec<u8> = (0..=255).collect();
+        let original = packet(Kind::Message, &binary);
+        assert_eq!(decode(&encode(&original)), Ok(original));
+    }
+
+    #[test]
+    fn too_short() {
+        assert_eq!(decode(&[]), Err(DecodeError::TooShort));
+        let bytes = encode(&packet(Kind::Message, &[]));
+        assert_eq!(decode(&bytes[..HEADER_SIZE - 1]), Err(DecodeError::TooShort));
+    }
+
+    #[test]
+    fn length_mismatch() {
+        let mut bytes = encode(&packet(Kind::Message, b"hello"));
+        bytes.pop();
+        assert_eq!(decode(&bytes), Err(DecodeError::LengthMismatch));
+        bytes.extend_from_slice(b"oo");
+        assert_eq!(decode(&bytes), Err(DecodeError::LengthMismatch));
+    }
+
+    #[test]
+    fn unknown_kind_and_version() {
+        let mut bytes = encode(&packet(Kind::Message, b"hello"));
+        bytes[1] = 0;
+        assert_eq!(decode(&bytes), Err(DecodeError::UnknownKind(0)));
+        bytes[1] = 200;
+        assert_eq!(decode(&bytes), Err(DecodeError::UnknownKind(200)));
+        bytes[0] = VERSION + 1;
+        assert_eq!(decode(&bytes), Err(DecodeError::UnsupportedVersion(VERSION + 1)));
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        let fields = ["alice", "", "привет мир"];
+        let decoded = decode_fields(&encode_fields(&fields)).unwrap();
+        assert_eq!(decoded, fields);
+        assert_eq!(decode_fields(&[]), Some(Vec::new()));
+    }
+
+    #[test]
+    fn fields_bad_lengths() {
+        let bytes = encode_fields(&["alice", "bob"]);
+        //Оборванная длина поля
+        assert_eq!(decode_fields(&bytes[..2]), None);
+        //Длина больше чем осталось данных
+        assert_eq!(decode_fields(&bytes[..bytes.len() - 1]), None);
+        //Огромная длина из поврежденных данных
+        assert_eq!(decode_fields(&[0xff, 0xff, 0xff, 0xff, b'a']), None);
+        //Не UTF8
+        assert_eq!(decode_fields(&[0, 0, 0, 1, 0xff]), None);
+    }
+
+    #[test]
+    fn resend_and_ping_payloads() {
+        assert_eq!(Packet::resend(&[1, 2, 0xffff_ffff]).sequences(), vec![1, 2, 0xffff_ffff]);
+        assert_eq!(Packet::ping(7).ping_id(), Some(7));
+        assert_eq!(packet(Kind::Ping, &[1, 2, 3]).ping_id(), None);
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::fragment;
+use crate::socket::Datagram;
+use crate::{Kind, Packet};
+
+//Через сколько миллисекунд без подтверждения пакет отправляется повторно в первый раз.
+//Каждая следующая попытка ждет в два раза дольше
+const INITIAL_RETRY_IN_MILLIS: u64 = 200;
+//Сколько раз отправлять пакет прежде чем считать что получатель недоступен
+const MAX_ATTEMPTS: u32 = 6;
+//На сколько номеров пакет может опережать последний полученный без пропусков.
+//Пакеты дальше не подтверждаются и не запоминаются: отправитель повторит их когда пропуск заполнится,
+// а чужой не сможет заставить нас помнить сколько угодно номеров
+const MAX_AHEAD: u32 = 1024;
+
+//Отправленный пакет который еще не подтвердил получатель
+#[derive(Debug)]
+struct Pending {
+    //Датаграммы на которые был разбит пакет
+    datagrams: Vec<Vec<u8>>,
+    attempts: u32,
+    retry_at: Instant,
+}
+
+//Номера пакетов уже полученных от одного отправителя.
+//Хранит последний номер до которого получено все без пропусков и номера пришедшие после пропуска
+#[derive(Debug, Default)]
+struct Received {
+    contiguous: u32,
+    ahead: BTreeSet<u32>,
+}
+
+impl Received {
+    //Поместится ли номер в окно. Уже полученные номера тоже в окне, ведь на них нужно ответить подтверждением
+    fn fits(&self, sequence: u32) -> bool {
+        sequence <= self.contiguous.saturating_add(MAX_AHEAD)
+    }
+
+    //Запоминает номер пакета. Возвращает false если такой пакет уже приходил
+    fn insert(&mut self, sequence: u32) -> bool {
+        if sequence <= self.contiguous || !self.ahead.insert(sequence) {
+            return false;
+        }
+        //Сдвигаем границу пока номера идут подряд
+        while self.ahead.remove(&(self.contiguous + 1)) {
+            self.contiguous += 1;
+        }
+        true
+    }
+}
+
+//Надежная доставка поверх UDP: подтверждения, повторная отправка и отбрасывание дубликатов.
+//Сам сокет не хранит, поэтому один и тот же экземпляр можно использовать с разными
+// реализациями Datagram
+#[derive(Debug, Default)]
+pub struct Reliable {
+    //Неподтвержденные пакеты по адресу получателя и номеру пакета
+    pending: HashMap<(SocketAddr, u32), Pending>,
+    //Номера полученных пакетов по адресу отправителя
+    received: HashMap<SocketAddr, Received>,
+    //Номер который получат фрагменты следующего слишком большого пакета
+    fragment_id: u32,
+}
+
+impl Reliable {
+    pub fn new() -> Reliable {
+        Reliable::default()
+    }
+
+    //Отправляет пакет, при необходимости разбив его на фрагменты.
+    //Если тип пакета требует подтверждения то пакет запоминается
+    // и будет отправляться повторно пока не придет подтверждение.
+    //Такой пакет запоминается даже если отправить его сразу не удалось.
+    //Пакет больше MAX_PACKET_SIZE не отправляется и не запоминается
+    pub fn send<S: Datagram>(&mut self, socket: &S, address: SocketAddr, packet: &Packet) -> io::Result<()> {
+        self.fragment_id = self.fragment_id.wrapping_add(1);
+        let datagrams = fragment::split(crate::encode(packet), self.fragment_id)?;
+        let result = send_all(socket, &datagrams, address);
+        if packet.kind.is_reliable() {
+            self.pending.insert((address, packet.sequence), Pending {
+                datagrams,
+                attempts: 1,
+                retry_at: Instant::now() + Duration::from_millis(INITIAL_RETRY_IN_MILLIS),
+            });
+        }
+        result
+    }
+
+    //Обрабатывает пришедший пакет: отвечает подтверждением и снимает с ожидания подтвержденные пакеты.
+    //Возвращает true если пакет нужно передать приложению и false для подтверждений и дубликатов
+    pub fn receive<S: Datagram>(&mut self, socket: &S, address: SocketAddr, packet: &Packet) -> io::Result<bool> {
+        if packet.kind == Kind::Ack {
+            self.pending.remove(&(address, packet.sequence));
+            return Ok(false);
+        }
+        if !packet.kind.is_reliable() {
+            return Ok(true);
+        }
+        let received = self.received.entry(address).or_default();
+        if !received.fits(packet.sequence) {
+            return Ok(false);
+        }
+        //Подтверждаем даже дубликаты, ведь наше прошлое подтверждение могло потеряться
+        acknowledge(socket, address, packet)?;
+        Ok(received.insert(packet.sequence))
+    }
+
+    //Только подтверждает пакет, ничего о нем не запоминая. Так отвечают тем от кого пакеты не принимаются,
+    // чтобы они не повторяли их без конца, а мы не тратили на них память
+    pub fn acknowledge<S: Datagram>(&self, socket: &S, address: SocketAddr, packet: &Packet) -> io::Result<()> {
+        if packet.kind.is_reliable() {
+            acknowledge(socket, address, packet)?;
+        }
+        Ok(())
+    }
+
+    //Ждет ли пакет подтверждения от получателя
+    pub fn is_pending(&self, address: SocketAddr, sequence: u32) -> bool {
+        self.pending.contains_key(&(address, sequence))
+    }
+
+    //Сразу же повторно отправляет пакет если получатель сообщил что не получил его
+    pub fn resend<S: Datagram>(&mut self, socket: &S, address: SocketAddr, sequence: u32) -> io::Result<()> {
+        match self.pending.get(&(address, sequence)) {
+            Some(pending) => send_all(socket, &pending.datagrams, address),
+            //Пакет уже подтвержден или мы отказались его доставлять
+            None => Ok(()),
+        }
+    }
+
+    //Повторно отправляет пакеты время ожидания подтверждения которых истекло.
+    //Возвращает адреса получателей которые так и не подтвердили пакет за MAX_ATTEMPTS попыток
+    pub fn retransmit<S: Datagram>(&mut self, socket: &S) -> Vec<SocketAddr> {
+        let now = Instant::now();
+        let mut unreachable = Vec::new();
+        for (&(address, _), pending) in self.pending.iter_mut() {
+            if pending.retry_at > now {
+                continue;
+            }
+            if pending.attempts >= MAX_ATTEMPTS {
+                unreachable.push(address);
+                continue;
+            }
+            //Ошибку отправки не обрабатываем отдельно: пакет просто будет отправлен еще раз
+            let _ = send_all(socket, &pending.datagrams, address);
+            pending.attempts += 1;
+            pending.retry_at = now + Duration::from_millis(INITIAL_RETRY_IN_MILLIS << (pending.attempts - 1));
+        }
+        for address in &unreachable {
+            self.forget(address);
+        }
+        unreachable.sort();
+        unreachable.dedup();
+        unreachable
+    }
+
+    //Забывает все что связано с адресом, например когда клиент отключился
+    pub fn forget(&mut self, address: &SocketAddr) {
+        self.pending.retain(|&(a, _), _| a != *address);
+        self.received.remove(address);
+    }
+}
+
+//В подтверждении номер пакета совпадает с номером подтверждаемого пакета
+fn acknowledge<S: Datagram>(socket: &S, address: SocketAddr, packet: &Packet) -> io::Result<()> {
+    let ack = Packet::new(Kind::Ack, crate::SERVER_ID, packet.sequence, Vec::new());
+    socket.send_to(&crate::encode(&ack), address).map(|_| ())
+}
+
+//Отправляет все датаграммы пакета по адресу
+fn send_all<S: Datagram>(socket: &S, datagrams: &[Vec<u8>], address: SocketAddr) -> io::Result<()> {
+    for datagram in datagrams {
+        socket.send_to(datagram, address)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LossySocket, Reassembler};
+    use std::net::UdpSocket;
+
+    //Сокет на loopback который не ждет датаграмм дольше нескольких миллисекунд
+    fn socket() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(5))).unwrap();
+        socket
+    }
+
+    //Читает из сокета все что успело прийти
+    fn drain<S: Datagram>(socket: &S, reassembler: &mut Reassembler) -> Vec<(SocketAddr, Packet)> {
+        let mut packets = Vec::new();
+        let mut buf = [0u8; 2048];
+        while let Ok((size, address)) = socket.recv_from(&mut buf) {
+            let packet = crate::decode(&buf[..size]).and_then(|p| reassembler.accept(address, p)).unwrap();
+            packets.extend(packet.map(|packet| (address, packet)));
+        }
+        packets
+    }
+
+    #[test]
+    fn received_suppresses_duplicates() {
+        let mut received = Received::default();
+        assert!(received.insert(2));
+        assert!(!received.insert(2));
+        assert!(received.insert(1));
+        assert!(!received.insert(1));
+        assert!(received.insert(3));
+        assert_eq!(received.contiguous, 3);
+        assert!(received.ahead.is_empty());
+    }
+
+    #[test]
+    fn ignores_packets_far_ahead() {
+        let (sender, receiver) = (socket(), socket());
+        let sender_address = sender.local_addr().unwrap();
+        let mut receiving = Reliable::new();
+        let far = Packet::new(Kind::Message, 1, MAX_AHEAD + 1, Vec::new());
+        assert!(!receiving.receive(&receiver, sender_address, &far).unwrap());
+        //Такой пакет не подтвержден, поэтому отправитель повторит его
+        let mut buf = [0u8; 64];
+        assert!(sender.recv_from(&mut buf).is_err());
+        let near = Packet::new(Kind::Message, 1, MAX_AHEAD, Vec::new());
+        assert!(receiving.receive(&receiver, sender_address, &near).unwrap());
+        assert!(sender.recv_from(&mut buf).is_ok());
+        //Когда пропуск заполнен окно сдвигается
+        for sequence in 1..MAX_AHEAD {
+            receiving.receive(&receiver, sender_address, &Packet::new(Kind::Message, 1, sequence, Vec::new())).unwrap();
+        }
+        assert!(receiving.receive(&receiver, sender_address, &far).unwrap());
+        assert!(receiving.received[&sender_address].ahead.is_empty());
+    }
+
+    #[test]
+    fn delivers_every_packet_exactly_once_over_lossy_socket() {
+        //Теряется каждая пятая датаграмма от отправителя и каждое четвертое подтверждение,
+        // поэтому получатель увидит и пропуски и повторы
+        let receiver_socket = socket();
+        let receiver_address = receiver_socket.local_addr().unwrap();
+        let sender = LossySocket::new(socket(), 5);
+        let receiver = LossySocket::new(receiver_socket, 4);
+        let (mut sending, mut receiving) = (Reliable::new(), Reliable::new());
+        let (mut sender_fragments, mut receiver_fragments) = (Reassembler::new(), Reassembler::new());
+        //Одно сообщение не помещается в датаграмму и идет фрагментами
+        let payloads: Vec<Vec<u8>> = (1..=20u32)
+            .map(|sequence| if sequence == 7 { vec![7; 3000] } else { sequence.to_be_bytes().to_vec() })
+            .collect();
+        for (sequence, payload) in (1..).zip(&payloads) {
+            let packet = Packet::new(Kind::Message, 1, sequence, payload.clone());
+            sending.send(&sender, receiver_address, &packet).unwrap();
+        }
+        let mut delivered = Vec::new();
+        let mut arrived = 0;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !sending.pending.is_empty() && Instant::now() < deadline {
+            for (address, packet) in drain(&receiver, &mut receiver_fragments) {
+                arrived += 1;
+                if receiving.receive(&receiver, address, &packet).unwrap() {
+                    delivered.push(packet);
+                }
+            }
+            for (address, ack) in drain(&sender, &mut sender_fragments) {
+                assert!(!sending.receive(&sender, address, &ack).unwrap());
+            }
+            assert!(sending.retransmit(&sender).is_empty());
+        }
+        assert!(sending.pending.is_empty(), "not every packet was acknowledged");
+        delivered.sort_by_key(|packet| packet.sequence);
+        let sequences: Vec<u32> = delivered.iter().map(|packet| packet.sequence).collect();
+        assert_eq!(sequences, (1..=20).collect::<Vec<u32>>());
+        let received: Vec<Vec<u8>> = delivered.into_iter().map(|packet| packet.payload).collect();
+        assert_eq!(received, payloads);
+        //Из-за потерянных подтверждений часть пакетов пришла дважды, но приложение их не увидело
+        assert!(arrived > 20);
+    }
+
+    #[test]
+    fn gives_up_on_silent_receiver() {
+        let sender = socket();
+        let silent = socket().local_addr().unwrap();
+        let mut sending = Reliable::new();
+        sending.send(&sender, silent, &Packet::new(Kind::Message, 1, 1, Vec::new())).unwrap();
+        //Неподтверждаемые пакеты не запоминаются
+        sending.send(&sender, silent, &Packet::ping(1)).unwrap();
+        assert_eq!(sending.pending.len(), 1);
+        for _ in 0..MAX_ATTEMPTS {
+            for pending in sending.pending.values_mut() {
+                pending.retry_at = Instant::now();
+            }
+            let unreachable = sending.retransmit(&sender);
+            if !unreachable.is_empty() {
+                assert_eq!(unreachable, vec![silent]);
+                assert!(sending.pending.is_empty());
+                return;
+            }
+        }
+        panic!("receiver was never reported unreachable");
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::Packet;
+
+//Сколько миллисекунд держать пакеты пришедшие раньше своей очереди прежде чем
+// отказаться от ожидания пропущенных пакетов
+const HOLD_TIMEOUT_IN_MILLIS: u64 = 3000;
+//Как часто повторять запрос на повторную отправку пропущенных пакетов
+const RESEND_INTERVAL_IN_MILLIS: u64 = 200;
+//Сколько миллисекунд после того как мы перестали ждать пропущенный пакет он еще может прийти
+// повторной отправкой. Должно быть дольше чем отправитель повторяет неподтвержденные пакеты
+const LATE_TIMEOUT_IN_MILLIS: u64 = 30_000;
+//Сколько пропущенных номеров запоминать за один раз. Огромный пропуск в нумерации все равно не дождаться
+const MAX_SKIPPED: u32 = 1024;
+
+//Буфер который восстанавливает порядок пакетов по их номерам.
+//Пакеты пришедшие раньше своей очереди придерживаются пока не придут пропущенные
+#[derive(Debug)]
+pub struct Reorder {
+    //Номер пакета который должен быть отдан приложению следующим
+    next: u32,
+    //Пакеты пришедшие раньше своей очереди и время когда пришел первый из них
+    held: BTreeMap<u32, Packet>,
+    held_since: Option<Instant>,
+    //Когда последний раз просили повторить пропущенные пакеты
+    requested_at: Option<Instant>,
+    //Номера пакетов которые мы перестали ждать и когда это случилось.
+    //Если такой пакет все же придет то отдаем его приложению хоть и не по порядку
+    skipped: BTreeMap<u32, Instant>,
+}
+
+impl Default for Reorder {
+    fn default() -> Reorder {
+        Reorder::new()
+    }
+}
+
+impl Reorder {
+    pub fn new() -> Reorder {
+        Reorder {
+            //Нумерация пакетов у отправителя начинается с 1
+            next: 1,
+            held: BTreeMap::new(),
+            held_since: None,
+            requested_at: None,
+            skipped: BTreeMap::new(),
+        }
+    }
+
+    //Принимает пакет и возвращает пакеты которые теперь можно отдать приложению по порядку
+    pub fn push(&mut self, packet: Packet) -> Vec<Packet> {
+        //Пакет из прошлого. Если мы его так и не дождались то он опоздал, но все равно нужен приложению
+        if packet.sequence < self.next {
+            return match self.skipped.remove(&packet.sequence) {
+                Some(_) => vec![packet],
+                None => Vec::new(),
+            };
+        }
+        if self.held.is_empty() {
+            self.held_since = Some(Instant::now());
+        }
+        self.held.insert(packet.sequence, packet);
+        self.release()
+    }
+
+    //Номера пропущенных пакетов если пора снова попросить отправителя их повторить
+    pub fn missing(&mut self) -> Vec<u32> {
+        let now = Instant::now();
+        let last = match self.held.keys().next_back() {
+            Some(&last) => last,
+            None => return Vec::new(),
+        };
+        if let Some(at) = self.requested_at {
+            if now.duration_since(at) < Duration::from_millis(RESEND_INTERVAL_IN_MILLIS) {
+                return Vec::new();
+            }
+        }
+        self.requested_at = Some(now);
+        (self.next..last)
+            .filter(|sequence| !self.held.contains_key(sequence))
+            .collect()
+    }
+
+    //Если пропущенные пакеты так и не пришли за HOLD_TIMEOUT_IN_MILLIS то перестаем их ждать
+    // и возвращаем все придержанные пакеты
+    pub fn expire(&mut self) -> Vec<Packet> {
+        let now = Instant::now();
+        self.skipped.retain(|_, &mut since| now.duration_since(since) < Duration::from_millis(LATE_TIMEOUT_IN_MILLIS));
+        match self.held_since {
+            Some(since) if since.elapsed() >= Duration::from_millis(HOLD_TIMEOUT_IN_MILLIS) => {
+                if let Some(&first) = self.held.keys().next() {
+                    let skipped = (first - self.next).min(MAX_SKIPPED);
+                    self.skipped.extend((first - skipped..first).map(|sequence| (sequence, now)));
+                    self.next = first;
+                }
+                self.release()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    //Забирает из буфера пакеты идущие подряд начиная с next
+    fn release(&mut self) -> Vec<Packet> {
+        let mut ready = Vec::new();
+        while let Some(packet) = self.held.remove(&self.next) {
+            ready.push(packet);
+            self.next = self.next.wrapping_add(1);
+        }
+        if self.held.is_empty() {
+            self.held_since = None;
+            self.requested_at = None;
+        } else if !ready.is_empty() {
+            self.held_since = Some(Instant::now());
+        }
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Kind;
+
+    fn packet(sequence: u32) -> Packet {
+        Packet::new(Kind::Message, 1, sequence, Vec::new())
+    }
+
+    fn sequences(packets: Vec<Packet>) -> Vec<u32> {
+        packets.into_iter().map(|packet| packet.sequence).collect()
+    }
+
+    //Делает вид что пакеты придержаны уже дольше HOLD_TIMEOUT_IN_MILLIS
+    fn age(reorder: &mut Reorder) {
+        reorder.held_since = Some(Instant::now() - Duration::from_millis(HOLD_TIMEOUT_IN_MILLIS));
+    }
+
+    #[test]
+    fn restores_order() {
+        let mut reorder = Reorder::new();
+        assert_eq!(sequences(reorder.push(packet(2))), Vec::<u32>::new());
+        assert_eq!(sequences(reorder.push(packet(4))), Vec::<u32>::new());
+        assert_eq!(reorder.missing(), vec![1, 3]);
+        assert_eq!(sequences(reorder.push(packet(1))), vec![1, 2]);
+        assert_eq!(sequences(reorder.push(packet(3))), vec![3, 4]);
+        assert!(reorder.missing().is_empty());
+    }
+
+    #[test]
+    fn delivers_late_packet_after_giving_up() {
+        let mut reorder = Reorder::new();
+        reorder.push(packet(1));
+        reorder.push(packet(3));
+        reorder.push(packet(4));
+        assert!(reorder.expire().is_empty());
+        age(&mut reorder);
+        assert_eq!(sequences(reorder.expire()), vec![3, 4]);
+        //Повторная отправка пропущенного пакета пришла уже после того как мы перестали его ждать
+        assert_eq!(sequences(reorder.push(packet(2))), vec![2]);
+        //Повтор того же опоздавшего пакета и уже отданные пакеты больше не отдаются
+        assert!(reorder.push(packet(2)).is_empty());
+        assert!(reorder.push(packet(3)).is_empty());
+        assert_eq!(sequences(reorder.push(packet(5))), vec![5]);
+    }
+
+    #[test]
+    fn forgets_skipped_after_late_timeout() {
+        let mut reorder = Reorder::new();
+        reorder.push(packet(2));
+        age(&mut reorder);
+        assert_eq!(sequences(reorder.expire()), vec![2]);
+        let long_ago = Instant::now() - Duration::from_millis(LATE_TIMEOUT_IN_MILLIS);
+        for since in reorder.skipped.values_mut() {
+            *since = long_ago;
+        }
+        reorder.expire();
+        assert!(reorder.push(packet(1)).is_empty());
+    }
+
+    #[test]
+    fn limits_skipped_numbers() {
+        let mut reorder = Reorder::new();
+        reorder.push(packet(u32::MAX));
+        age(&mut reorder);
+        assert_eq!(sequences(reorder.expire()), vec![u32::MAX]);
+        assert_eq!(reorder.skipped.len(), MAX_SKIPPED as usize);
+    }
+}
@@ -0,0 +1,749 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::identity::{self, Fingerprint, Identity, IDENTITY_KEY_SIZE, SIGNATURE_SIZE};
+use crate::socket::Datagram;
+use crate::VERSION;
+
+//Клиент предлагает обмен ключами и присылает свой временный открытый ключ
+const HELLO: u8 = 1;
+//Ответ сервера на HELLO с его временным открытым ключом, постоянным ключом сервера
+// и подписью обоих временных ключей постоянным ключом
+const WELCOME: u8 = 2;
+//Зашифрованный пакет
+const SEALED: u8 = 3;
+//Ответ сервера на HELLO без верной метки: клиент должен повторить HELLO с этой меткой.
+//Так сервер убеждается что клиент получает ответы на свой адрес, прежде чем тратить на него память
+const HELLO_VERIFY: u8 = 4;
+//Размер открытого ключа X25519
+const KEY_SIZE: usize = 32;
+//Размер метки которую сервер выдает в HELLO_VERIFY
+const COOKIE_SIZE: usize = 32;
+//Сколько секунд действует метка. Принимается метка текущего и прошлого периода
+const COOKIE_PERIOD_IN_SECS: u64 = 30;
+//Размер датаграммы WELCOME
+const WELCOME_SIZE: usize = 2 + KEY_SIZE + IDENTITY_KEY_SIZE + SIGNATURE_SIZE;
+//С этого начинается подписываемое сервером сообщение, чтобы подпись нельзя было использовать в другом месте
+const TRANSCRIPT_LABEL: &[u8] = b"udp chat key exchange";
+//Заголовок зашифрованной датаграммы: версия (1) + тип (1) + номер (8)
+const SEALED_HEADER_SIZE: usize = 10;
+//Размер кода аутентичности который AEAD добавляет к шифротексту
+const TAG_SIZE: usize = 16;
+//На сколько байт зашифрованная датаграмма больше исходной
+pub const SEAL_OVERHEAD: usize = SEALED_HEADER_SIZE + TAG_SIZE;
+//Сколько номеров до самого большого из принятых помнит защита от повторов.
+//Датаграммы старше этого отбрасываются даже если раньше не приходили
+const REPLAY_WINDOW: u64 = 64;
+//Сколько клиент ждет ответа сервера на предложение обменяться ключами
+const HANDSHAKE_TIMEOUT_IN_MILLIS: u64 = 3000;
+//Как часто клиент повторяет предложение если ответа нет
+const HELLO_INTERVAL_IN_MILLIS: u64 = 500;
+
+//Ошибка разбора или расшифровки защищенной датаграммы
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecureError {
+    //Датаграмма не похожа ни на обмен ключами ни на зашифрованный пакет
+    Malformed,
+    //С этим адресом еще не было обмена ключами
+    UnknownPeer,
+    //Открытый ключ собеседника не дает общего секрета
+    WeakKey,
+    //Датаграмма не расшифровывается нашим ключом: ее подделали или повредили
+    Forged,
+    //Датаграмма с этим номером уже приходила
+    Replayed,
+    //Отпечаток ключа сервера не совпадает с тем которому доверяет клиент
+    Untrusted(Fingerprint),
+}
+
+impl fmt::Display for SecureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SecureError::Malformed => write!(f, "malformed secure datagram"),
+            SecureError::UnknownPeer => write!(f, "no key exchange with this peer"),
+            SecureError::WeakKey => write!(f, "weak public key"),
+            SecureError::Forged => write!(f, "datagram can't be decrypted"),
+            SecureError::Replayed => write!(f, "datagram is replayed"),
+            SecureError::Untrusted(fingerprint) => {
+                write!(f, "server fingerprint {} doesn't match the pinned one", fingerprint)
+            }
+        }
+    }
+}
+
+impl Error for SecureError {}
+
+//Номера недавно принятых датаграмм. Бит i в seen означает что пришла датаграмма с номером highest - i
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    highest: u64,
+    seen: u64,
+}
+
+impl ReplayWindow {
+    //Можно ли принять датаграмму с таким номером
+    fn check(&self, counter: u64) -> bool {
+        if counter > self.highest {
+            return true;
+        }
+        let age = self.highest - counter;
+        age < REPLAY_WINDOW && self.seen & (1 << age) == 0
+    }
+
+    //Запоминает номер расшифрованной датаграммы
+    fn accept(&mut self, counter: u64) {
+        if counter > self.highest {
+            let shift = counter - self.highest;
+            self.seen = if shift < REPLAY_WINDOW { self.seen << shift } else { 0 };
+            self.seen |= 1;
+            self.highest = counter;
+        } else {
+            self.seen |= 1 << (self.highest - counter);
+        }
+    }
+}
+
+//Зашифрованный канал с одним собеседником. У каждого направления свой ключ и своя нумерация
+struct Channel {
+    sealer: ChaCha20Poly1305,
+    opener: ChaCha20Poly1305,
+    //Номер последней отправленной датаграммы. Он же служит одноразовым числом шифра
+    sent: u64,
+    replay: ReplayWindow,
+    //Открытые ключи этого обмена. Нужны чтобы узнать повторный HELLO и повторить на него ответ
+    local: [u8; KEY_SIZE],
+    remote: [u8; KEY_SIZE],
+    //Отпечаток ключа сервера. Есть только у клиента
+    server: Option<Fingerprint>,
+    last_seen: Instant,
+}
+
+impl Channel {
+    //Выводит ключи обоих направлений из общего секрета обмена X25519
+    fn new(secret: EphemeralSecret, remote: [u8; KEY_SIZE], initiator: bool) -> Result<Channel, SecureError> {
+        let local = PublicKey::from(&secret).to_bytes();
+        let shared = secret.diffie_hellman(&PublicKey::from(remote));
+        if !shared.was_contributory() {
+            return Err(SecureError::WeakKey);
+        }
+        //Оба открытых ключа входят в соль, поэтому ключи привязаны к конкретному обмену
+        let (client, server) = if initiator { (local, remote) } else { (remote, local) };
+        let mut salt = client.to_vec();
+        salt.extend_from_slice(&server);
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes());
+        let mut upstream = [0u8; 32];
+        let mut downstream = [0u8; 32];
+        hkdf.expand(b"udp chat client to server", &mut upstream).map_err(|_| SecureError::WeakKey)?;
+        hkdf.expand(b"udp chat server to client", &mut downstream).map_err(|_| SecureError::WeakKey)?;
+        let (sealing, opening) = if initiator { (upstream, downstream) } else { (downstream, upstream) };
+        Ok(Channel {
+            sealer: ChaCha20Poly1305::new(&sealing.into()),
+            opener: ChaCha20Poly1305::new(&opening.into()),
+            sent: 0,
+            replay: ReplayWindow::default(),
+            local,
+            remote,
+            server: None,
+            last_seen: Instant::now(),
+        })
+    }
+
+    //Выделяет номер следующей датаграмме. Зашифровать ее можно позже и в другом потоке
+    fn sealer(&mut self) -> Sealer {
+        self.sent += 1;
+        Sealer { cipher: self.sealer.clone(), counter: self.sent }
+    }
+
+    //Расшифровывает датаграмму. Номер запоминается только если датаграмма подлинная,
+    // иначе чужой мог бы сдвинуть окно и заставить нас отбрасывать настоящие датаграммы
+    fn open(&mut self, bytes: &[u8]) -> Result<Vec<u8>, SecureError> {
+        if bytes.len() < SEAL_OVERHEAD {
+            return Err(SecureError::Malformed);
+        }
+        let mut counter = [0u8; 8];
+        counter.copy_from_slice(&bytes[2..SEALED_HEADER_SIZE]);
+        let counter = u64::from_be_bytes(counter);
+        if !self.replay.check(counter) {
+            return Err(SecureError::Replayed);
+        }
+        let (header, ciphertext) = bytes.split_at(SEALED_HEADER_SIZE);
+        let plaintext = self.opener
+            .decrypt(&nonce(counter), Payload { msg: ciphertext, aad: header })
+            .map_err(|_| SecureError::Forged)?;
+        self.replay.accept(counter);
+        self.last_seen = Instant::now();
+        Ok(plaintext)
+    }
+}
+
+impl fmt::Debug for Channel {
+    //Ключи в лог не попадают
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Channel").field("sent", &self.sent).field("replay", &self.replay).finish()
+    }
+}
+
+//Ключ и номер одной исходящей датаграммы. Номер выделяется под мьютексом канала,
+// а само шифрование уже не требует доступа к каналу
+pub struct Sealer {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl Sealer {
+    //Шифрует датаграмму и добавляет к ней заголовок с номером
+    pub fn seal(self, plaintext: &[u8]) -> Vec<u8> {
+        let mut header = vec![VERSION, SEALED];
+        header.extend_from_slice(&self.counter.to_be_bytes());
+        let ciphertext = self.cipher
+            .encrypt(&nonce(self.counter), Payload { msg: plaintext, aad: &header })
+            .unwrap_or_default();
+        header.extend_from_slice(&ciphertext);
+        header
+    }
+}
+
+impl fmt::Debug for Sealer {
+    //Ключ в лог не попадает
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sealer").field("counter", &self.counter).finish()
+    }
+}
+
+//Обмен ключами который начал клиент и на который сервер еще не ответил
+struct Handshake {
+    secret: EphemeralSecret,
+    local: [u8; KEY_SIZE],
+    //Отпечаток ключа сервера которому доверяет клиент
+    pin: Option<Fingerprint>,
+    //Метка из HELLO_VERIFY. Без нее сервер не отвечает на HELLO своим ключом
+    cookie: Option<[u8; COOKIE_SIZE]>,
+}
+
+impl Handshake {
+    //Датаграмма HELLO с нашим ключом и меткой сервера если она уже есть
+    fn hello(&self) -> Vec<u8> {
+        let mut hello = handshake(HELLO, &self.local);
+        if let Some(ref cookie) = self.cookie {
+            hello.extend_from_slice(cookie);
+        }
+        hello
+    }
+}
+
+impl fmt::Debug for Handshake {
+    //Секрет в лог не попадает
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Handshake").field("pin", &self.pin).finish()
+    }
+}
+
+//Сокет который шифрует все отправляемые датаграммы и расшифровывает полученные.
+//Перед обменом сообщениями клиент и сервер договариваются о ключах через обмен X25519,
+// после чего каждая датаграмма шифруется ChaCha20-Poly1305 со своим номером.
+//Сервер подписывает обмен своим постоянным ключом, поэтому встать посередине незаметно нельзя.
+//Сам по себе обмен X25519 никак не аутентифицирован: до подписанного WELCOME (user-017)
+// шифрование защищало только от подслушивания, а не от атаки посредника.
+// Поэтому подпись нельзя делать необязательной, а клиенту стоит закреплять отпечаток сервера.
+//Ключ на HELLO сервер отдает только после того как клиент вернет выданную ему метку,
+// поэтому с поддельного адреса нельзя ни занять память сервера, ни оборвать чужой канал.
+//Подделанные, поврежденные и повторно пришедшие датаграммы отбрасываются
+#[derive(Debug)]
+pub struct Secure<S: Datagram> {
+    inner: S,
+    //Постоянный ключ сервера. У клиента его нет
+    identity: Option<Identity>,
+    channels: Mutex<HashMap<SocketAddr, Channel>>,
+    //Обмены ключами которые начал клиент
+    handshakes: Mutex<HashMap<SocketAddr, Handshake>>,
+    //Каналы по HELLO с новым ключом. Прежний канал с этим адресом заменяется только когда по новому
+    // придет первая подлинная датаграмма, иначе поддельный HELLO обрывал бы чужую сессию
+    proposed: Mutex<HashMap<SocketAddr, Channel>>,
+    //Секрет которым сервер подписывает метки HELLO_VERIFY. Сами метки сервер не хранит
+    cookie_secret: [u8; 32],
+    //От этого момента отсчитываются периоды действия меток
+    started_at: Instant,
+}
+
+impl<S: Datagram> Secure<S> {
+    //Сторона сервера. Каналы появляются когда клиенты присылают HELLO в open
+    pub fn new(inner: S, identity: Identity) -> Secure<S> {
+        let mut cookie_secret = [0u8; 32];
+        OsRng.fill_bytes(&mut cookie_secret);
+        Secure {
+            inner,
+            identity: Some(identity),
+            channels: Mutex::new(HashMap::new()),
+            handshakes: Mutex::new(HashMap::new()),
+            proposed: Mutex::new(HashMap::new()),
+            cookie_secret,
+            started_at: Instant::now(),
+        }
+    }
+
+    //Сторона клиента. Договаривается о ключах с сервером и возвращает готовый к работе сокет.
+    //Если указан pin то сервер должен доказать что у него ключ именно с этим отпечатком,
+    // иначе принимается любой ключ и его отпечаток можно узнать через fingerprint.
+    //У сокета должен быть установлен таймаут чтения, иначе ожидание ответа будет бесконечным
+    pub fn connect(inner: S, server: SocketAddr, pin: Option<Fingerprint>) -> io::Result<Secure<S>> {
+        let secure = Secure {
+            inner,
+            identity: None,
+            channels: Mutex::new(HashMap::new()),
+            handshakes: Mutex::new(HashMap::new()),
+            proposed: Mutex::new(HashMap::new()),
+            cookie_secret: [0u8; 32],
+            started_at: Instant::now(),
+        };
+        let started_at = Instant::now();
+        let mut hello_sent_at = None;
+        let mut buf = [0u8; 4096];
+        //Почему не приняли последний ответ. Ответы не прошедшие проверку пропускаем и ждем дальше,
+        // чтобы подделанный ответ не мешал дождаться настоящего
+        let mut refused = None;
+        while started_at.elapsed() < Duration::from_millis(HANDSHAKE_TIMEOUT_IN_MILLIS) {
+            //HELLO или ответ на него могли потеряться, поэтому время от времени повторяем его
+            if hello_sent_at.is_none_or(|at: Instant| at.elapsed() >= Duration::from_millis(HELLO_INTERVAL_IN_MILLIS)) {
+                secure.hello(server, pin)?;
+                hello_sent_at = Some(Instant::now());
+            }
+            match secure.inner.recv_from(&mut buf) {
+                Ok((count, source)) if source == server => match secure.open(&buf[..count], source) {
+                    Ok(_) if secure.is_connected(server) => return Ok(secure),
+                    Ok(_) => {}
+                    Err(e) => refused = Some(e),
+                },
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(match refused {
+            Some(e @ SecureError::Untrusted(_)) => io::Error::new(io::ErrorKind::PermissionDenied, e),
+            Some(SecureError::Forged) => io::Error::new(io::ErrorKind::InvalidData, "server signature is invalid"),
+            _ => io::Error::new(io::ErrorKind::TimedOut, "server didn't answer the key exchange"),
+        })
+    }
+
+    //Предлагает серверу обменяться ключами не дожидаясь ответа. Ответ обработает open.
+    //Прежние ключи сервера забываются, а повторный вызов повторяет то же предложение
+    // вместе с меткой если сервер ее уже выдал.
+    //Так клиент заново договаривается о ключах с сервером который перезапустился
+    pub fn hello(&self, server: SocketAddr, pin: Option<Fingerprint>) -> io::Result<()> {
+        self.lock().remove(&server);
+        let mut handshakes = self.handshakes.lock().unwrap_or_else(|e| e.into_inner());
+        let pending = handshakes.entry(server).or_insert_with(|| {
+            let secret = EphemeralSecret::random_from_rng(OsRng);
+            let local = PublicKey::from(&secret).to_bytes();
+            Handshake { secret, local, pin, cookie: None }
+        });
+        let hello = pending.hello();
+        drop(handshakes);
+        self.inner.send_to(&hello, server).map(|_| ())
+    }
+
+    //Договорились ли мы о ключах с собеседником
+    pub fn is_connected(&self, peer: SocketAddr) -> bool {
+        self.lock().contains_key(&peer)
+    }
+
+    //Отпечаток ключа сервера с которым клиент договорился о ключах
+    pub fn fingerprint(&self, server: SocketAddr) -> Option<Fingerprint> {
+        self.lock().get(&server).and_then(|channel| channel.server)
+    }
+
+    //Сокет через который на самом деле уходят датаграммы
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    //Разбирает датаграмму пришедшую с адреса source. Возвращает расшифрованные данные
+    // или None если это был обмен ключами: на HELLO мы уже ответили, а по WELCOME договорились о ключах
+    pub fn open(&self, bytes: &[u8], source: SocketAddr) -> Result<Option<Vec<u8>>, SecureError> {
+        if bytes.len() < 2 || bytes[0] != VERSION {
+            return Err(SecureError::Malformed);
+        }
+        match bytes[1] {
+            HELLO => {
+                let (remote, cookie) = parse_hello(bytes).ok_or(SecureError::Malformed)?;
+                self.welcome(remote, cookie, source)?;
+                Ok(None)
+            }
+            HELLO_VERIFY => {
+                self.verify(bytes, source)?;
+                Ok(None)
+            }
+            WELCOME => {
+                self.complete(bytes, source)?;
+                Ok(None)
+            }
+            SEALED => self.open_sealed(bytes, source).map(Some),
+            _ => Err(SecureError::Malformed),
+        }
+    }
+
+    //Забывает ключи клиента который отключился
+    pub fn forget(&self, address: &SocketAddr) {
+        self.lock().remove(address);
+        self.lock_proposed().remove(address);
+    }
+
+    //Забывает ключи собеседников от которых ничего не приходило дольше timeout.
+    //Возвращает их адреса, чтобы можно было забыть и все остальное что с ними связано
+    pub fn expire(&self, timeout: Duration) -> Vec<SocketAddr> {
+        let mut expired = Vec::new();
+        for channels in [&mut *self.lock(), &mut *self.lock_proposed()] {
+            channels.retain(|&address, channel| {
+                let alive = channel.last_seen.elapsed() < timeout;
+                if !alive {
+                    expired.push(address);
+                }
+                alive
+            });
+        }
+        expired.sort();
+        expired.dedup();
+        expired
+    }
+
+    //Расшифровывает датаграмму каналом с адресом source. Если не вышло то пробует канал
+    // по последнему HELLO с этого адреса и при успехе заменяет им прежний канал
+    fn open_sealed(&self, bytes: &[u8], source: SocketAddr) -> Result<Vec<u8>, SecureError> {
+        let mut channels = self.lock();
+        let error = match channels.get_mut(&source).map(|channel| channel.open(bytes)) {
+            Some(Ok(plaintext)) => return Ok(plaintext),
+            Some(Err(e)) => e,
+            None => SecureError::UnknownPeer,
+        };
+        let mut proposed = self.lock_proposed();
+        let plaintext = match proposed.get_mut(&source).map(|channel| channel.open(bytes)) {
+            Some(Ok(plaintext)) => plaintext,
+            _ => return Err(error),
+        };
+        if let Some(channel) = proposed.remove(&source) {
+            channels.insert(source, channel);
+        }
+        Ok(plaintext)
+    }
+
+    //Отвечает на HELLO своим ключом. Без верной метки только выдаем метку и ничего не запоминаем.
+    //На повторный HELLO с тем же ключом повторяем прежний ответ, а новый ключ значит
+    // что клиент перезапустился и для него готовится новый канал
+    fn welcome(&self, remote: [u8; KEY_SIZE], cookie: Option<[u8; COOKIE_SIZE]>, source: SocketAddr) -> Result<(), SecureError> {
+        //Клиент не отвечает на HELLO
+        let identity = self.identity.as_ref().ok_or(SecureError::Malformed)?;
+        if !cookie.is_some_and(|cookie| self.check_cookie(&cookie, &remote, source)) {
+            //Ответ не больше самого HELLO, поэтому с поддельным адресом отправителя им никого не завалить
+            let mut verify = vec![VERSION, HELLO_VERIFY];
+            verify.extend_from_slice(&self.cookie(&remote, source, self.cookie_period()));
+            let _ = self.inner.send_to(&verify, source);
+            return Ok(());
+        }
+        let channels = self.lock();
+        let mut proposed = self.lock_proposed();
+        let known = channels.get(&source).into_iter().chain(proposed.get(&source))
+            .find(|channel| channel.remote == remote)
+            .map(|channel| channel.local);
+        let local = match known {
+            Some(local) => local,
+            None => {
+                let channel = Channel::new(EphemeralSecret::random_from_rng(OsRng), remote, false)?;
+                let local = channel.local;
+                proposed.insert(source, channel);
+                local
+            }
+        };
+        drop(proposed);
+        drop(channels);
+        let mut welcome = handshake(WELCOME, &local);
+        welcome.extend_from_slice(&identity.public_key());
+        welcome.extend_from_slice(&identity.sign(&transcript(&remote, &local)));
+        //Если ответ не дойдет то клиент повторит HELLO
+        let _ = self.inner.send_to(&welcome, source);
+        Ok(())
+    }
+
+    //Запоминает метку которую выдал сервер и сразу повторяет HELLO уже с ней
+    fn verify(&self, bytes: &[u8], source: SocketAddr) -> Result<(), SecureError> {
+        if bytes.len() != 2 + COOKIE_SIZE {
+            return Err(SecureError::Malformed);
+        }
+        let mut handshakes = self.handshakes.lock().unwrap_or_else(|e| e.into_inner());
+        let pending = handshakes.get_mut(&source).ok_or(SecureError::UnknownPeer)?;
+        pending.cookie = Some(read_array(&bytes[2..]));
+        let hello = pending.hello();
+        drop(handshakes);
+        //Если HELLO не дойдет то клиент повторит его сам
+        let _ = self.inner.send_to(&hello, source);
+        Ok(())
+    }
+
+    //Номер текущего периода действия меток
+    fn cookie_period(&self) -> u64 {
+        self.started_at.elapsed().as_secs() / COOKIE_PERIOD_IN_SECS
+    }
+
+    //Метка для клиента с адресом source и ключом remote в период period.
+    //Подделать ее без секрета сервера нельзя, поэтому хранить выданные метки не нужно
+    fn cookie(&self, remote: &[u8; KEY_SIZE], source: SocketAddr, period: u64) -> [u8; COOKIE_SIZE] {
+        self.cookie_mac(remote, source, period).finalize().into_bytes().into()
+    }
+
+    //Выдавали ли мы эту метку клиенту с этим адресом и ключом в текущем или прошлом периоде
+    fn check_cookie(&self, cookie: &[u8; COOKIE_SIZE], remote: &[u8; KEY_SIZE], source: SocketAddr) -> bool {
+        let period = self.cookie_period();
+        [Some(period), period.checked_sub(1)]
+            .iter()
+            .flatten()
+            .any(|&period| self.cookie_mac(remote, source, period).verify_slice(cookie).is_ok())
+    }
+
+    fn cookie_mac(&self, remote: &[u8; KEY_SIZE], source: SocketAddr, period: u64) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.cookie_secret).expect("HMAC accepts keys of any size");
+        mac.update(source.to_string().as_bytes());
+        mac.update(remote);
+        mac.update(&period.to_be_bytes());
+        mac
+    }
+
+    //Проверяет ответ сервера на наш HELLO и заводит канал с ним.
+    //Если ответ не прошел проверку то ждем следующего, вдруг этот подделан
+    fn complete(&self, bytes: &[u8], source: SocketAddr) -> Result<(), SecureError> {
+        let (remote, identity, signature) = parse_welcome(bytes).ok_or(SecureError::Malformed)?;
+        let mut handshakes = self.handshakes.lock().unwrap_or_else(|e| e.into_inner());
+        let pending = handshakes.get(&source).ok_or(SecureError::UnknownPeer)?;
+        let fingerprint = Fingerprint::of(&identity);
+        if !identity::verify(&identity, &transcript(&pending.local, &remote), &signature) {
+            return Err(SecureError::Forged);
+        }
+        if pending.pin.is_some_and(|pin| pin != fingerprint) {
+            return Err(SecureError::Untrusted(fingerprint));
+        }
+        let pending = handshakes.remove(&source).ok_or(SecureError::UnknownPeer)?;
+        drop(handshakes);
+        let mut channel = Channel::new(pending.secret, remote, true)?;
+        channel.server = Some(fingerprint);
+        self.lock().insert(source, channel);
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SocketAddr, Channel>> {
+        //Канал не может остаться в испорченном состоянии, поэтому отравленный мьютекс не страшен
+        self.channels.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    //Всегда берется после lock, если нужны оба мьютекса
+    fn lock_proposed(&self) -> std::sync::MutexGuard<'_, HashMap<SocketAddr, Channel>> {
+        self.proposed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<S: Datagram> Datagram for Secure<S> {
+    fn send_to(&self, buf: &[u8], address: SocketAddr) -> io::Result<usize> {
+        let sealer = match self.lock().get_mut(&address) {
+            Some(channel) => channel.sealer(),
+            None => return Err(io::Error::new(io::ErrorKind::NotConnected, SecureError::UnknownPeer)),
+        };
+        self.inner.send_sealed(sealer, buf, address)?;
+        Ok(buf.len())
+    }
+
+    //Возвращает только расшифрованные данные. Обмен ключами обрабатывается внутри
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut sealed = [0u8; 4096];
+        loop {
+            let (count, source) = self.inner.recv_from(&mut sealed)?;
+            match self.open(&sealed[..count], source) {
+                Ok(Some(plaintext)) => {
+                    let count = plaintext.len().min(buf.len());
+                    buf[..count].copy_from_slice(&plaintext[..count]);
+                    return Ok((count, source));
+                }
+                Ok(None) => continue,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        }
+    }
+}
+
+//Датаграмма обмена ключами: версия, тип и открытый ключ
+fn handshake(kind: u8, key: &[u8; KEY_SIZE]) -> Vec<u8> {
+    let mut bytes = vec![VERSION, kind];
+    bytes.extend_from_slice(key);
+    bytes
+}
+
+//Открытый ключ и метка сервера если она есть из датаграммы HELLO
+fn parse_hello(bytes: &[u8]) -> Option<([u8; KEY_SIZE], Option<[u8; COOKIE_SIZE]>)> {
+    if bytes.len() < 2 || bytes[0] != VERSION || bytes[1] != HELLO {
+        return None;
+    }
+    let key = &bytes[2..];
+    match key.len() {
+        KEY_SIZE => Some((read_array(key), None)),
+        n if n == KEY_SIZE + COOKIE_SIZE => Some((read_array(&key[..KEY_SIZE]), Some(read_array(&key[KEY_SIZE..])))),
+        _ => None,
+    }
+}
+
+//Временный ключ сервера, постоянный ключ сервера и подпись из датаграммы WELCOME
+fn parse_welcome(bytes: &[u8]) -> Option<([u8; KEY_SIZE], [u8; IDENTITY_KEY_SIZE], [u8; SIGNATURE_SIZE])> {
+    if bytes.len() != WELCOME_SIZE || bytes[0] != VERSION || bytes[1] != WELCOME {
+        return None;
+    }
+    let (remote, rest) = bytes[2..].split_at(KEY_SIZE);
+    let (identity, signature) = rest.split_at(IDENTITY_KEY_SIZE);
+    Some((read_array(remote), read_array(identity), read_array(signature)))
+}
+
+fn read_array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut array = [0u8; N];
+    array.copy_from_slice(bytes);
+    array
+}
+
+//Что подписывает сервер: оба временных ключа этого обмена.
+//Подпись нельзя перенести в другой обмен, так как ключ клиента каждый раз новый
+fn transcript(client: &[u8; KEY_SIZE], server: &[u8; KEY_SIZE]) -> Vec<u8> {
+    let mut transcript = TRANSCRIPT_LABEL.to_vec();
+    transcript.extend_from_slice(client);
+    transcript.extend_from_slice(server);
+    transcript
+}
+
+//Одноразовое число шифра из номера датаграммы. Номера не повторяются пока жив канал,
+// а у каждого направления свой ключ, поэтому пара ключ и число никогда не повторяется
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    fn socket() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        socket
+    }
+
+    //Клиент который не ждет ответа сервера сам, чтобы тест мог пересылать датаграммы по одной
+    fn client(inner: UdpSocket) -> Secure<UdpSocket> {
+        Secure {
+            inner,
+            identity: None,
+            channels: Mutex::new(HashMap::new()),
+            handshakes: Mutex::new(HashMap::new()),
+            proposed: Mutex::new(HashMap::new()),
+            cookie_secret: [0u8; 32],
+            started_at: Instant::now(),
+        }
+    }
+
+    //Читает одну датаграмму из сокета и передает ее в open
+    fn deliver(to: &Secure<UdpSocket>) -> Result<Option<Vec<u8>>, SecureError> {
+        let mut buf = [0u8; 4096];
+        let (count, source) = to.inner.recv_from(&mut buf).unwrap();
+        to.open(&buf[..count], source)
+    }
+
+    //Полный обмен ключами: HELLO, HELLO_VERIFY, HELLO с меткой и WELCOME
+    fn handshake(client: &Secure<UdpSocket>, server: &Secure<UdpSocket>) {
+        let address = server.inner.local_addr().unwrap();
+        client.hello(address, None).unwrap();
+        assert_eq!(deliver(server), Ok(None));
+        assert_eq!(deliver(client), Ok(None));
+        assert_eq!(deliver(server), Ok(None));
+        assert_eq!(deliver(client), Ok(None));
+        assert!(client.is_connected(address));
+    }
+
+    #[test]
+    fn hello_without_cookie_allocates_nothing() {
+        let server = Secure::new(socket(), Identity::generate());
+        let stranger = socket();
+        let address = server.inner.local_addr().unwrap();
+        let mut hello = vec![VERSION, HELLO];
+        hello.extend_from_slice(&[7u8; KEY_SIZE]);
+        stranger.send_to(&hello, address).unwrap();
+        assert_eq!(deliver(&server), Ok(None));
+        assert!(server.lock().is_empty());
+        assert!(server.lock_proposed().is_empty());
+        //Ответ не больше запроса
+        let mut buf = [0u8; 4096];
+        let (count, _) = stranger.recv_from(&mut buf).unwrap();
+        assert_eq!(count, hello.len());
+        assert_eq!(buf[1], HELLO_VERIFY);
+        //Метка не подходит к другому ключу
+        let mut forged = vec![VERSION, HELLO];
+        forged.extend_from_slice(&[8u8; KEY_SIZE]);
+        forged.extend_from_slice(&buf[2..count]);
+        stranger.send_to(&forged, address).unwrap();
+        assert_eq!(deliver(&server), Ok(None));
+        assert!(server.lock_proposed().is_empty());
+        stranger.recv_from(&mut buf).unwrap();
+        assert_eq!(buf[1], HELLO_VERIFY);
+    }
+
+    #[test]
+    fn exchanges_keys_after_cookie_round_trip() {
+        let server = Secure::new(socket(), Identity::generate());
+        let client = client(socket());
+        handshake(&client, &server);
+        let address = server.inner.local_addr().unwrap();
+        client.send_to(b"hello", address).unwrap();
+        assert_eq!(deliver(&server), Ok(Some(b"hello".to_vec())));
+        let client_address = client.inner.local_addr().unwrap();
+        server.send_to(b"welcome", client_address).unwrap();
+        assert_eq!(deliver(&client), Ok(Some(b"welcome".to_vec())));
+    }
+
+    #[test]
+    fn expire_reports_forgotten_peers() {
+        let server = Secure::new(socket(), Identity::generate());
+        let client = client(socket());
+        handshake(&client, &server);
+        assert!(server.expire(Duration::from_secs(60)).is_empty());
+        assert_eq!(server.expire(Duration::ZERO), vec![client.inner.local_addr().unwrap()]);
+        assert!(server.lock_proposed().is_empty());
+        assert!(server.expire(Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn new_hello_keeps_established_channel_until_proven() {
+        let server = Secure::new(socket(), Identity::generate());
+        let inner = socket();
+        let old = client(inner.try_clone().unwrap());
+        handshake(&old, &server);
+        let address = server.inner.local_addr().unwrap();
+        old.send_to(b"first", address).unwrap();
+        assert_eq!(deliver(&server), Ok(Some(b"first".to_vec())));
+        //С того же адреса кто то договаривается о новых ключах
+        let new = client(inner);
+        handshake(&new, &server);
+        //Прежний канал все еще работает
+        old.send_to(b"still here", address).unwrap();
+        assert_eq!(deliver(&server), Ok(Some(b"still here".to_vec())));
+        //Первая подлинная датаграмма по новому каналу заменяет прежний
+        new.send_to(b"restarted", address).unwrap();
+        assert_eq!(deliver(&server), Ok(Some(b"restarted".to_vec())));
+        old.send_to(b"stale", address).unwrap();
+        assert_eq!(deliver(&server), Err(SecureError::Forged));
+    }
+}
@@ -0,0 +1,81 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::secure::Sealer;
+
+//Создает UDP сокет на адресе. Сокет на неуказанном IPv6 адресе (::) делаем двухстековым,
+// чтобы он принимал датаграммы и от IPv4 и от IPv6 клиентов. IPv4 клиенты в этом случае
+// видны как адреса вида ::ffff:1.2.3.4
+pub fn bind(address: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(address), Type::DGRAM, Some(Protocol::UDP))?;
+    if address.is_ipv6() && address.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    socket.bind(&address.into())?;
+    Ok(socket.into())
+}
+
+//Абстракция над UDP сокетом. Позволяет подменить настоящий сокет,
+// например сокетом который теряет часть пакетов
+pub trait Datagram {
+    //Отправляет датаграмму по адресу
+    fn send_to(&self, buf: &[u8], address: SocketAddr) -> io::Result<usize>;
+    //Читает датаграмму и возвращает количество считанных байт и адрес отправителя
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+    //Шифрует датаграмму и отправляет ее. Так Secure отправляет данные через этот сокет.
+    //Сокет может отложить шифрование, например чтобы зашифровать рассылку параллельно
+    fn send_sealed(&self, sealer: Sealer, buf: &[u8], address: SocketAddr) -> io::Result<usize> {
+        self.send_to(&sealer.seal(buf), address)
+    }
+}
+
+impl Datagram for UdpSocket {
+    fn send_to(&self, buf: &[u8], address: SocketAddr) -> io::Result<usize> {
+        //На некоторых системах send_to для подключенного через connect сокета возвращает ошибку,
+        // поэтому если адрес совпадает с тем к которому мы подключены используем send
+        match self.peer_addr() {
+            Ok(peer) if peer == address => self.send(buf),
+            _ => UdpSocket::send_to(self, buf, address),
+        }
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+}
+
+//Сокет который теряет каждую drop_every отправленную датаграмму.
+//Нужен чтобы проверить доставку сообщений при потере пакетов не выходя из процесса
+pub struct LossySocket<S: Datagram> {
+    inner: S,
+    drop_every: usize,
+    sent: AtomicUsize,
+}
+
+impl<S: Datagram> LossySocket<S> {
+    pub fn new(inner: S, drop_every: usize) -> LossySocket<S> {
+        LossySocket {
+            inner,
+            drop_every,
+            sent: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<S: Datagram> Datagram for LossySocket<S> {
+    fn send_to(&self, buf: &[u8], address: SocketAddr) -> io::Result<usize> {
+        let count = self.sent.fetch_add(1, Ordering::SeqCst) + 1;
+        //Делаем вид что датаграмма ушла, но на самом деле ее никто не получит
+        if self.drop_every != 0 && count.is_multiple_of(self.drop_every) {
+            return Ok(buf.len());
+        }
+        self.inner.send_to(buf, address)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.inner.recv_from(buf)
+    }
+}
@@ -0,0 +1,157 @@
+//Сравнивает задержку рассылки сообщения всем участникам комнаты у сервера на потоках
+// и асинхронного сервера на tokio. Запуск: cargo bench --bench fan_out
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use protocol::{Datagram, Fingerprint, Kind, Packet, Reliable, Secure};
+use server::{ChatServer, Level, Runtime};
+
+//Сколько клиентов подключаем в каждом замере
+const CLIENT_COUNTS: [usize; 3] = [10, 50, 200];
+//Сколько сообщений рассылаем в каждом замере
+const ROUNDS: usize = 20;
+//Сколько ждать пока сообщение дойдет до всех клиентов
+const ROUND_TIMEOUT: Duration = Duration::from_secs(5);
+//Сколько времени клиенты не должны получать пакетов чтобы считать что сервер закончил рассылку оповещений о входе.
+//Больше самой долгой паузы между повторными отправками
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(7);
+
+//Клиент который только подтверждает пакеты сервера и запоминает какие сообщения пришли
+struct Bot {
+    socket: Secure<UdpSocket>,
+    server: SocketAddr,
+    reliable: Reliable,
+    sequence: u32,
+    //Сервер пустил бота в чат
+    accepted: bool,
+}
+
+impl Bot {
+    fn connect(server: SocketAddr, fingerprint: Fingerprint, nickname: &str) -> Bot {
+        let socket = UdpSocket::bind("127.0.0.1:0").expect("can't bind bot socket");
+        socket.connect(server).expect("can't connect bot socket");
+        socket.set_read_timeout(Some(Duration::from_millis(100))).expect("can't set bot read timeout");
+        let socket = Secure::connect(socket, server, Some(fingerprint)).expect("bot can't exchange keys");
+        socket.inner().set_nonblocking(true).expect("can't make bot socket non blocking");
+        let mut bot = Bot { socket, server, reliable: Reliable::new(), sequence: 0, accepted: false };
+        bot.send(Kind::Join, protocol::encode_fields(&[nickname, ""]));
+        bot
+    }
+
+    fn send(&mut self, kind: Kind, payload: Vec<u8>) {
+        self.sequence += 1;
+        let packet = Packet::new(kind, protocol::SERVER_ID, self.sequence, payload);
+        self.reliable.send(&self.socket, self.server, &packet).expect("can't send from bot");
+    }
+
+    //Читает все что пришло и возвращает типы новых пакетов
+    fn poll(&mut self) -> Vec<Kind> {
+        let mut kinds = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((count, _)) => {
+                    let packet = match protocol::decode(&buf[..count]) {
+                        Ok(packet) => packet,
+                        Err(_) => continue,
+                    };
+                    if self.reliable.receive(&self.socket, self.server, &packet).unwrap_or(false) {
+                        self.accepted |= packet.kind == Kind::Accepted;
+                        kinds.push(packet.kind);
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                //Датаграмму не удалось расшифровать
+                Err(ref e) if e.kind() == ErrorKind::InvalidData => continue,
+                Err(e) => panic!("bot can't read: {}", e),
+            }
+        }
+        self.reliable.retransmit(&self.socket);
+        kinds
+    }
+}
+
+//Ждет пока каждый бот не получит пакет типа kind. Возвращает false если не дождались
+fn wait_for_all(bots: &mut [Bot], kind: Kind) -> bool {
+    let started = Instant::now();
+    let mut got = vec![false; bots.len()];
+    while got.iter().any(|got| !got) {
+        if started.elapsed() > ROUND_TIMEOUT {
+            return false;
+        }
+        for (index, bot) in bots.iter_mut().enumerate() {
+            if bot.poll().contains(&kind) {
+                got[index] = true;
+            }
+        }
+    }
+    true
+}
+
+//Ждет пока сервер не пустит в чат всех ботов. Возвращает false если не дождались
+fn wait_for_join(bots: &mut [Bot]) -> bool {
+    let started = Instant::now();
+    while bots.iter().any(|bot| !bot.accepted) {
+        if started.elapsed() > ROUND_TIMEOUT {
+            return false;
+        }
+        for bot in bots.iter_mut() {
+            bot.poll();
+        }
+    }
+    true
+}
+
+//Возвращает медиану и максимум задержки рассылки одного сообщения всем клиентам
+fn measure(runtime: Runtime, clients: usize) -> (Duration, Duration) {
+    let handle = ChatServer::new()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .max_clients(clients)
+        .runtime(runtime)
+        .log_level(Level::Warn)
+        .spawn()
+        .expect("can't start server");
+    //Пока подключаются новые боты уже вошедшие должны подтверждать оповещения о входе.
+    //Иначе сервер решит что они недоступны и забудет их ключи
+    let mut bots: Vec<Bot> = Vec::with_capacity(clients);
+    for index in 0..clients {
+        let bot = Bot::connect(handle.local_addr(), handle.fingerprint(), &format!("bot{}", index));
+        for bot in bots.iter_mut() {
+            bot.poll();
+        }
+        bots.push(bot);
+    }
+    assert!(wait_for_join(&mut bots), "not all bots joined");
+    //Даем серверу разослать оповещения о входе и получить на них подтверждения.
+    //Часть оповещений теряется когда все клиенты входят разом, поэтому ждем пока не закончатся
+    // их повторные отправки, иначе они попадут в замеры
+    let mut quiet = Instant::now();
+    while quiet.elapsed() < SETTLE_TIMEOUT {
+        for bot in bots.iter_mut() {
+            if !bot.poll().is_empty() {
+                quiet = Instant::now();
+            }
+        }
+    }
+    let mut latencies = Vec::with_capacity(ROUNDS);
+    for round in 0..ROUNDS {
+        let started = Instant::now();
+        bots[0].send(Kind::Message, protocol::encode_fields(&[protocol::DEFAULT_ROOM, &format!("round {}", round)]));
+        assert!(wait_for_all(&mut bots, Kind::Message), "message didn't reach every bot");
+        latencies.push(started.elapsed());
+    }
+    handle.shutdown().expect("can't stop server");
+    latencies.sort();
+    (latencies[latencies.len() / 2], latencies[latencies.len() - 1])
+}
+
+fn main() {
+    println!("{:>8} {:>8} {:>12} {:>12}", "runtime", "clients", "median", "max");
+    for &clients in CLIENT_COUNTS.iter() {
+        for &runtime in [Runtime::Threads, Runtime::Tokio].iter() {
+            let (median, max) = measure(runtime, clients);
+            println!("{:>8} {:>8} {:>12?} {:>12?}", format!("{:?}", runtime), clients, median, max);
+        }
+    }
+}
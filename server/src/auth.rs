@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use protocol::write_secret;
+use rand_core::OsRng;
+
+use crate::config::Config;
+use crate::error::ServerError;
+
+//Сколько неудачных попыток входа подряд можно сделать с одного IP адреса прежде чем его заблокируют
+const MAX_LOGIN_FAILURES: u32 = 5;
+//Через сколько времени без новых ошибок счетчик неудачных попыток сбрасывается
+const FAILURE_WINDOW_IN_SECS: u64 = 600;
+//На сколько блокируется адрес с которого слишком часто ошибались паролем
+const BAN_IN_SECS: u64 = 300;
+
+//Пользователи которым разрешено входить в чат. Хранятся в файле по строке на пользователя
+// вида `ник:хеш пароля`. Сами пароли нигде не хранятся, только их хеши Argon2
+pub struct Users {
+    hashes: HashMap<String, String>,
+}
+
+impl Users {
+    pub fn load(path: &Path) -> io::Result<Users> {
+        let text = fs::read_to_string(path)?;
+        let mut hashes = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (nickname, hash) = line.split_once(':').ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: expected `nickname:hash`", number + 1))
+            })?;
+            hashes.insert(nickname.trim().to_string(), hash.trim().to_string());
+        }
+        Ok(Users { hashes })
+    }
+
+    //Добавляет пользователя в файл или меняет пароль уже существующего
+    pub fn add(path: &Path, nickname: &str, password: &str) -> io::Result<()> {
+        //Двоеточие отделяет ник от хеша, а с пробелами в нике в чат все равно не войти
+        if nickname.is_empty() || nickname.contains(':') || nickname.chars().any(char::is_whitespace) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid nickname {}", nickname)));
+        }
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+            .map_err(|e| io::Error::other(e.to_string()))?
+            .to_string();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        //Старую запись этого пользователя убираем, остальные строки оставляем как есть
+        let mut lines: Vec<String> = text.lines()
+            .filter(|line| line.split_once(':').map(|(name, _)| name.trim()) != Some(nickname))
+            .map(str::to_string)
+            .collect();
+        lines.push(format!("{}:{}", nickname, hash));
+        //Хеши паролей должен читать только владелец файла, ведь по ним можно подбирать пароли не обращаясь к серверу
+        write_secret(path, &(lines.join("\n") + "\n"))
+    }
+
+    //Правильный ли пароль у пользователя
+    fn verify(&self, nickname: &str, password: &str) -> bool {
+        self.hashes
+            .get(nickname)
+            .and_then(|hash| PasswordHash::new(hash).ok())
+            .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+            .unwrap_or(false)
+    }
+}
+
+//Неудачные попытки входа с одного адреса
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+//Проверка паролей при входе в чат и блокировка адресов с которых пароль подбирают.
+//Если не задан ни файл пользователей ни общий токен то войти может кто угодно
+pub struct Auth {
+    users: Option<Users>,
+    //Общий для всех пароль. Подходит для любого ника
+    token: Option<String>,
+    failures: HashMap<IpAddr, Failures>,
+    //Заблокированные адреса и время когда блокировка снимается
+    banned: HashMap<IpAddr, Instant>,
+}
+
+impl Auth {
+    pub fn new(config: &Config) -> Result<Auth, ServerError> {
+        let users = match config.users {
+            Some(ref path) => Some(Users::load(path).map_err(|e| ServerError::Users(path.clone(), e))?),
+            None => None,
+        };
+        Ok(Auth {
+            users,
+            token: config.token.clone(),
+            failures: HashMap::new(),
+            banned: HashMap::new(),
+        })
+    }
+
+    //Пускает ли сервер без пароля
+    fn is_open(&self) -> bool {
+        self.users.is_none() && self.token.is_none()
+    }
+
+    //Заблокирован ли адрес
+    pub fn is_banned(&self, address: &IpAddr) -> bool {
+        self.banned.get(address).is_some_and(|until| Instant::now() < *until)
+    }
+
+    //Проверяет ник и пароль клиента с адреса address. Возвращает причину отказа
+    pub fn check(&mut self, address: IpAddr, nickname: &str, password: &str) -> Result<(), String> {
+        if self.is_banned(&address) {
+            return Err("too many failed logins, try again later".to_string());
+        }
+        if self.is_open() || self.accepts(nickname, password) {
+            self.failures.remove(&address);
+            return Ok(());
+        }
+        let failures = self.failures.entry(address).or_insert(Failures { count: 0, last: Instant::now() });
+        failures.count += 1;
+        failures.last = Instant::now();
+        if failures.count >= MAX_LOGIN_FAILURES {
+            warn!("{} is banned after {} failed logins", address, failures.count);
+            self.failures.remove(&address);
+            self.banned.insert(address, Instant::now() + Duration::from_secs(BAN_IN_SECS));
+        }
+        Err("wrong nickname or password".to_string())
+    }
+
+    //Подходит ли пароль. Токен сверяется за время не зависящее от того сколько символов совпало
+    fn accepts(&self, nickname: &str, password: &str) -> bool {
+        let token = self.token.as_ref().is_some_and(|token| {
+            token.len() == password.len()
+                && token.bytes().zip(password.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+        });
+        token || self.users.as_ref().is_some_and(|users| users.verify(nickname, password))
+    }
+
+    //Забывает истекшие блокировки и давние ошибки
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        self.banned.retain(|_, until| now < *until);
+        self.failures.retain(|_, failures| failures.last.elapsed() < Duration::from_secs(FAILURE_WINDOW_IN_SECS));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    const ADDRESS: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    fn with_token(token: &str) -> Auth {
+        Auth::new(&Config { token: Some(token.to_string()), ..Config::default() }).unwrap()
+    }
+
+    //Файл пользователей одного теста
+    fn users_file(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("users_{}_{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn open_without_users_and_token() {
+        let mut auth = Auth::new(&Config::default()).unwrap();
+        assert!(auth.check(ADDRESS, "alice", "").is_ok());
+        assert!(auth.check(ADDRESS, "bob", "anything").is_ok());
+    }
+
+    #[test]
+    fn bans_after_too_many_failures() {
+        let mut auth = with_token("secret");
+        for _ in 1..MAX_LOGIN_FAILURES {
+            assert_eq!(auth.check(ADDRESS, "alice", "guess"), Err("wrong nickname or password".to_string()));
+            assert!(!auth.is_banned(&ADDRESS));
+        }
+        assert!(auth.check(ADDRESS, "alice", "guess").is_err());
+        assert!(auth.is_banned(&ADDRESS));
+        //Пока адрес заблокирован не помогает даже правильный пароль, а другие адреса входят как обычно
+        assert_eq!(auth.check(ADDRESS, "alice", "secret"), Err("too many failed logins, try again later".to_string()));
+        assert!(auth.check("127.0.0.2".parse().unwrap(), "bob", "secret").is_ok());
+    }
+
+    #[test]
+    fn successful_login_resets_failures() {
+        let mut auth = with_token("secret");
+        for _ in 1..MAX_LOGIN_FAILURES {
+            assert!(auth.check(ADDRESS, "alice", "guess").is_err());
+        }
+        assert!(auth.check(ADDRESS, "alice", "secret").is_ok());
+        assert!(auth.check(ADDRESS, "alice", "guess").is_err());
+        assert!(!auth.is_banned(&ADDRESS));
+    }
+
+    #[test]
+    fn ban_expires() {
+        let mut auth = with_token("secret");
+        for _ in 0..MAX_LOGIN_FAILURES {
+            assert!(auth.check(ADDRESS, "alice", "guess").is_err());
+        }
+        //Переносим конец блокировки в прошлое вместо того чтобы ждать BAN_IN_SECS
+        auth.banned.insert(ADDRESS, Instant::now());
+        assert!(!auth.is_banned(&ADDRESS));
+        auth.expire();
+        assert!(auth.banned.is_empty());
+        assert!(auth.check(ADDRESS, "alice", "secret").is_ok());
+    }
+
+    #[test]
+    fn adds_and_verifies_users() {
+        let path = users_file("add");
+        Users::add(&path, "alice", "first").unwrap();
+        Users::add(&path, "bob", "bobs").unwrap();
+        //Повторное добавление меняет пароль, а не добавляет вторую строку
+        Users::add(&path, "alice", "second").unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let users = Users::load(&path).unwrap();
+        assert!(users.verify("alice", "second"));
+        assert!(!users.verify("alice", "first"));
+        assert!(users.verify("bob", "bobs"));
+        assert!(!users.verify("carol", "bobs"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_invalid_nicknames() {
+        let path = users_file("invalid");
+        for nickname in ["", "a:b", "two words"] {
+            assert_eq!(Users::add(&path, nickname, "password").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,298 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+//Сколько старых файлов журнала хранится после ротации: chat.log.1 самый новый из них, chat.log.5 самый старый
+const MAX_ROTATED_FILES: usize = 5;
+
+//Сообщение записанное в журнал
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    //Когда сервер получил сообщение, в миллисекундах от начала эпохи UNIX
+    pub timestamp: u64,
+    pub room: String,
+    //Ник автора
+    pub nickname: String,
+    pub text: String,
+}
+
+impl Record {
+    //Строка журнала: время, комната, ник и текст через табуляцию.
+    //В комнатах и никах пробелов быть не может, а в тексте табуляции и переводы строк экранируются
+    fn to_line(&self) -> String {
+        format!("{}\t{}\t{}\t{}\n", self.timestamp, self.room, self.nickname, escape(&self.text))
+    }
+
+    //Разбирает строку журнала. Возвращает None если строка повреждена
+    fn from_line(line: &str) -> Option<Record> {
+        let mut parts = line.splitn(4, '\t');
+        Some(Record {
+            timestamp: parts.next()?.parse().ok()?,
+            room: parts.next()?.to_string(),
+            nickname: parts.next()?.to_string(),
+            text: unescape(parts.next()?),
+        })
+    }
+}
+
+//Сообщение в виде `2024-01-31 18:05:00 #general alice: hello`. Время по UTC
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.timestamp / 1000;
+        let (year, month, day) = civil_date(seconds / 86400);
+        let time = seconds % 86400;
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02} #{} {}: {}",
+               year, month, day, time / 3600, time % 3600 / 60, time % 60, self.room, self.nickname, self.text)
+    }
+}
+
+//Журнал сообщений чата. Сообщения только дописываются в конец файла, поэтому после падения сервера
+// теряется не больше одной недописанной строки. Когда файл становится больше max_size
+// он переименовывается в chat.log.1, старые файлы сдвигаются, а самый старый удаляется
+pub struct ChatLog {
+    path: PathBuf,
+    max_size: u64,
+    file: File,
+    //Текущий размер файла в байтах
+    size: u64,
+}
+
+impl ChatLog {
+    //Открывает журнал для дописывания. Если файла нет то он будет создан
+    pub fn open(path: &Path, max_size: u64) -> io::Result<ChatLog> {
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        let mut size = file.metadata()?.len();
+        //Если сервер упал посреди записи то недописанную строку завершаем,
+        // иначе следующее сообщение склеится с ней и тоже потеряется
+        if size > 0 {
+            let mut last = [0u8; 1];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                file.write_all(b"\n")?;
+                size += 1;
+            }
+        }
+        Ok(ChatLog { path: path.to_path_buf(), max_size, file, size })
+    }
+
+    //Дописывает сообщение в конец журнала
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        let line = record.to_line();
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        //Строка пишется одним вызовом чтобы не перемешаться с чем то еще
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    //Дожидается пока все дописанное не окажется на диске, чтобы не потерять журнал если пропадет питание
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_data()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    //Сообщения отправленные с from включительно до to не включительно, от старых к новым.
+    //Время в миллисекундах от начала эпохи UNIX. Читает и файлы оставшиеся после ротации.
+    //Журнал можно читать пока сервер в него пишет
+    pub fn query(path: &Path, from: u64, to: u64) -> io::Result<Vec<Record>> {
+        let mut records = Vec::new();
+        ChatLog::scan(path, from, to, |record| records.push(record))?;
+        Ok(records)
+    }
+
+    //То же что query, но не собирает сообщения в память, а передает их по одному в visit
+    pub fn scan<F: FnMut(Record)>(path: &Path, from: u64, to: u64, mut visit: F) -> io::Result<()> {
+        for number in (0..=MAX_ROTATED_FILES).rev() {
+            let file = match File::open(rotated(path, number)) {
+                Ok(file) => file,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for line in BufReader::new(file).lines() {
+                //Поврежденные строки, например недописанную при падении сервера, пропускаем
+                match Record::from_line(&line?) {
+                    Some(record) if from <= record.timestamp && record.timestamp < to => visit(record),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        match fs::remove_file(rotated(&self.path, MAX_ROTATED_FILES)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+        for number in (1..MAX_ROTATED_FILES).rev() {
+            let older = rotated(&self.path, number);
+            if older.exists() {
+                fs::rename(older, rotated(&self.path, number + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(&self.path, 1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+//Путь к файлу журнала после number ротаций. Ноль это текущий файл
+fn rotated(path: &Path, number: usize) -> PathBuf {
+    if number == 0 {
+        return path.to_path_buf();
+    }
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", number));
+    PathBuf::from(name)
+}
+
+//Год, месяц и день по количеству дней от начала эпохи UNIX.
+//Алгоритм Говарда Хиннанта для пролептического григорианского календаря
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    //Пустой каталог для журнала одного теста
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chat_log_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn record(timestamp: u64, text: &str) -> Record {
+        Record { timestamp, room: "general".to_string(), nickname: "alice".to_string(), text: text.to_string() }
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        for text in ["", "plain", "tab\there", "two\nlines\r\n", "back\\slash \\t not a tab", "\\"] {
+            let escaped = escape(text);
+            assert!(!escaped.contains('\t') && !escaped.contains('\n') && !escaped.contains('\r'));
+            assert_eq!(unescape(&escaped), text);
+        }
+    }
+
+    #[test]
+    fn line_round_trip() {
+        let record = record(42, "multi\nline\twith tab");
+        let line = record.to_line();
+        assert_eq!(line.matches('\n').count(), 1);
+        assert_eq!(Record::from_line(line.trim_end_matches('\n')), Some(record));
+        assert_eq!(Record::from_line("not a number\tgeneral\talice\thi"), None);
+        assert_eq!(Record::from_line("42\tgeneral"), None);
+    }
+
+    #[test]
+    fn civil_date_matches_calendar() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(19_723), (2024, 1, 1));
+        assert_eq!(civil_date(19_753), (2024, 1, 31));
+        assert_eq!(record(1_706_724_300_000, "hello").to_string(), "2024-01-31 18:05:00 #general alice: hello");
+    }
+
+    #[test]
+    fn query_filters_by_time_range() {
+        let path = temp_dir("query").join("chat.log");
+        let mut log = ChatLog::open(&path, 1 << 20).unwrap();
+        for timestamp in 1..=5 {
+            log.append(&record(timestamp, &format!("message {}", timestamp))).unwrap();
+        }
+        log.flush().unwrap();
+        let timestamps = |from, to| -> Vec<u64> {
+            ChatLog::query(&path, from, to).unwrap().iter().map(|record| record.timestamp).collect()
+        };
+        assert_eq!(timestamps(2, 4), [2, 3]);
+        assert_eq!(timestamps(0, u64::MAX), [1, 2, 3, 4, 5]);
+        assert!(timestamps(6, 10).is_empty());
+    }
+
+    #[test]
+    fn rotates_and_drops_oldest_files() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("chat.log");
+        let line_size = record(10, "message").to_line().len() as u64;
+        //В каждый файл помещается ровно две строки
+        let mut log = ChatLog::open(&path, line_size * 2).unwrap();
+        for timestamp in 10..24 {
+            log.append(&record(timestamp, "message")).unwrap();
+        }
+        log.flush().unwrap();
+        for number in 0..=MAX_ROTATED_FILES {
+            assert_eq!(fs::metadata(rotated(&path, number)).unwrap().len(), line_size * 2, "file {}", number);
+        }
+        assert!(!rotated(&path, MAX_ROTATED_FILES + 1).exists());
+        //Самые старые сообщения удалены вместе с последним файлом
+        let timestamps: Vec<u64> = ChatLog::query(&path, 0, u64::MAX).unwrap().iter().map(|record| record.timestamp).collect();
+        assert_eq!(timestamps, (12..24).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn open_terminates_torn_line() {
+        let path = temp_dir("torn").join("chat.log");
+        fs::write(&path, format!("{}1\tgeneral\talice\tunfinis", record(0, "complete").to_line())).unwrap();
+        let mut log = ChatLog::open(&path, 1 << 20).unwrap();
+        log.append(&record(2, "after crash")).unwrap();
+        log.flush().unwrap();
+        let texts: Vec<String> = ChatLog::query(&path, 0, u64::MAX).unwrap().into_iter().map(|record| record.text).collect();
+        assert_eq!(texts, ["complete", "unfinis", "after crash"]);
+    }
+}
@@ -0,0 +1,252 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::auth::Auth;
+use crate::config::{Config, Runtime};
+use crate::error::ServerError;
+use crate::events::{ServerEvent, Subscribers};
+use crate::history::History;
+use crate::logger::{self, Level};
+use protocol::Fingerprint;
+
+//Как часто shutdown_when проверяет не пора ли остановить сервер
+const SIGNAL_CHECK_IN_MILLIS: u64 = 100;
+
+//Сервер чата который можно запустить из любой программы, например из интеграционного теста.
+//Настраивается цепочкой вызовов и запускается через spawn
+pub struct ChatServer {
+    config: Config,
+}
+
+impl ChatServer {
+    //Сервер с настройками по умолчанию
+    pub fn new() -> ChatServer {
+        ChatServer::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> ChatServer {
+        ChatServer { config }
+    }
+
+    //Адрес и порт на которых сервер будет слушать датаграммы. Порт 0 значит любой свободный порт,
+    // узнать какой именно можно через ServerHandle::local_addr
+    pub fn bind(mut self, address: SocketAddr) -> ChatServer {
+        self.config.bind_address = address.ip().to_string();
+        self.config.port = address.port();
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> ChatServer {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn read_timeout(mut self, read_timeout: Duration) -> ChatServer {
+        self.config.read_timeout = read_timeout;
+        self
+    }
+
+    pub fn max_clients(mut self, max_clients: usize) -> ChatServer {
+        self.config.max_clients = max_clients;
+        self
+    }
+
+    pub fn log_level(mut self, log_level: Level) -> ChatServer {
+        self.config.log_level = log_level;
+        self
+    }
+
+    //На потоках или на tokio будет работать сервер
+    pub fn runtime(mut self, runtime: Runtime) -> ChatServer {
+        self.config.runtime = runtime;
+        self
+    }
+
+    //Сколько последних сообщений комнаты получает тот кто в нее входит. Ноль отключает историю
+    pub fn history(mut self, history: usize) -> ChatServer {
+        self.config.history = history;
+        self
+    }
+
+    //Журнал в который записываются все сообщения комнат. Из него же восстанавливается история при запуске
+    pub fn chat_log(mut self, path: PathBuf) -> ChatServer {
+        self.config.chat_log = Some(path);
+        self
+    }
+
+    //Файл с постоянным ключом сервера. Если файла нет то он будет создан
+    pub fn identity(mut self, path: PathBuf) -> ChatServer {
+        self.config.identity = Some(path);
+        self
+    }
+
+    //Файл пользователей которым разрешено входить в чат. Пользователей добавляет Users::add
+    pub fn users(mut self, path: PathBuf) -> ChatServer {
+        self.config.users = Some(path);
+        self
+    }
+
+    //Общий пароль с которым может войти любой ник
+    pub fn token(mut self, token: &str) -> ChatServer {
+        self.config.token = Some(token.to_string());
+        self
+    }
+
+    //Открывает сокет и запускает потоки сервера. Сразу возвращает управление
+    pub fn spawn(self) -> Result<ServerHandle, ServerError> {
+        logger::set_level(self.config.log_level);
+        match self.config.runtime {
+            Runtime::Threads => self.spawn_threads(),
+            #[cfg(feature = "tokio")]
+            Runtime::Tokio => self.spawn_tokio(),
+            #[cfg(not(feature = "tokio"))]
+            Runtime::Tokio => Err(ServerError::Unsupported("server is built without the tokio feature")),
+        }
+    }
+
+    //Поток чтения из сокета передает датаграммы потоку рассылки
+    fn spawn_threads(self) -> Result<ServerHandle, ServerError> {
+        let identity = crate::load_identity(&self.config)?;
+        let fingerprint = identity.fingerprint();
+        let auth = Auth::new(&self.config)?;
+        let history = History::open(&self.config)?;
+        let socket = crate::create_socket(&self.config)?;
+        let local_addr = socket.local_addr()?;
+        let subscribers = Subscribers::new();
+        let stop = Arc::new(AtomicBool::new(false));
+        //Создаем односторонний канал с одним отправителем сообщений sx и множеством получателей rx
+        let (sx, rx) = mpsc::channel();
+        //Запускаем рассылку сообщений всем получателям в отдельном потоке
+        let broadcaster = crate::start_sender_thread(rx, socket.try_clone()?, identity, auth, history, self.config, subscribers.clone());
+        let receiver_stop = stop.clone();
+        let receiver = thread::spawn(move || crate::receive(&socket, &sx, &receiver_stop));
+        Ok(ServerHandle {
+            local_addr,
+            fingerprint,
+            stop,
+            subscribers,
+            receiver,
+            broadcaster: Some(broadcaster),
+        })
+    }
+
+    //Асинхронный сервер работает в отдельном потоке со своим рантаймом tokio
+    #[cfg(feature = "tokio")]
+    fn spawn_tokio(self) -> Result<ServerHandle, ServerError> {
+        let identity = crate::load_identity(&self.config)?;
+        let fingerprint = identity.fingerprint();
+        let auth = Auth::new(&self.config)?;
+        let history = History::open(&self.config)?;
+        let socket = crate::create_socket(&self.config)?;
+        //tokio работает только с неблокирующими сокетами
+        socket.set_nonblocking(true)?;
+        let local_addr = socket.local_addr()?;
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+        let subscribers = Subscribers::new();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (config, server_subscribers, server_stop) = (self.config, subscribers.clone(), stop.clone());
+        let receiver = thread::spawn(move || runtime.block_on(async move {
+            let socket = tokio::net::UdpSocket::from_std(socket)?;
+            crate::tokio_server::serve(socket, identity, auth, history, config, server_subscribers, server_stop).await
+        }));
+        Ok(ServerHandle {
+            local_addr,
+            fingerprint,
+            stop,
+            subscribers,
+            receiver,
+            broadcaster: None,
+        })
+    }
+}
+
+impl Default for ChatServer {
+    fn default() -> ChatServer {
+        ChatServer::new()
+    }
+}
+
+//Управление запущенным сервером
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    fingerprint: Fingerprint,
+    //Флаг который просит поток чтения из сокета завершиться
+    stop: Arc<AtomicBool>,
+    subscribers: Subscribers,
+    receiver: JoinHandle<Result<(), ServerError>>,
+    //Отдельного потока рассылки нет у асинхронного сервера
+    broadcaster: Option<JoinHandle<Result<(), ServerError>>>,
+}
+
+impl ServerHandle {
+    //Адрес на котором сервер на самом деле слушает датаграммы
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    //Отпечаток ключа сервера который клиенты могут закрепить
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    //Канал в который будут приходить события чата начиная с этого момента
+    pub fn events(&self) -> Receiver<ServerEvent> {
+        self.subscribers.subscribe()
+    }
+
+    //Останавливает сервер и ждет завершения его потоков
+    pub fn shutdown(self) -> Result<(), ServerError> {
+        self.stop.store(true, Ordering::SeqCst);
+        //Будим поток чтения пустой датаграммой чтобы не ждать таймаута чтения
+        let _ = UdpSocket::bind(unspecified(self.local_addr))
+            .and_then(|socket| socket.send_to(&[], loopback(self.local_addr)));
+        self.wait()
+    }
+
+    //Ждет пока не будет поднят флаг signal, например по сигналу от операционной системы, и останавливает сервер.
+    //Если сервер остановился раньше сам то возвращает ошибку из-за которой он остановился
+    pub fn shutdown_when(self, signal: &AtomicBool) -> Result<(), ServerError> {
+        while !signal.load(Ordering::SeqCst) {
+            if self.receiver.is_finished() {
+                return self.wait();
+            }
+            thread::sleep(Duration::from_millis(SIGNAL_CHECK_IN_MILLIS));
+        }
+        self.shutdown()
+    }
+
+    //Ждет пока сервер не остановится. Возвращает ошибку из-за которой он остановился
+    pub fn wait(self) -> Result<(), ServerError> {
+        let result = self.receiver.join().unwrap_or(Err(ServerError::ThreadPanicked));
+        //Поток рассылки завершается сам когда поток чтения закрывает канал
+        let flushed = match self.broadcaster {
+            Some(broadcaster) => broadcaster.join().unwrap_or(Err(ServerError::ThreadPanicked)),
+            None => Ok(()),
+        };
+        result.and(flushed)
+    }
+}
+
+//Любой адрес того же семейства что и address
+fn unspecified(address: SocketAddr) -> SocketAddr {
+    match address {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+//Адрес по которому до сервера можно достучаться с этой же машины
+fn loopback(address: SocketAddr) -> SocketAddr {
+    if !address.ip().is_unspecified() {
+        return address;
+    }
+    match address {
+        SocketAddr::V4(_) => (Ipv4Addr::LOCALHOST, address.port()).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::LOCALHOST, address.port()).into(),
+    }
+}
@@ -0,0 +1,278 @@
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::logger::Level;
+
+//Подсказка по параметрам командной строки
+pub const USAGE: &str = "Usage: server [OPTIONS]
+       server add-user <FILE> <NICKNAME>   add a user to FILE or change their password,
+                                           the password is read from stdin
+       server log <FILE> [FROM [TO]]       print messages from the chat log FILE sent
+                                           between FROM and TO, both in seconds since
+                                           the UNIX epoch (default all of them)
+
+Options:
+    --config <FILE>         read options from FILE with lines like `port = 7777`
+    --bind <ADDRESS>        address to listen on, 0.0.0.0 for all IPv4 interfaces,
+                            :: for IPv4 and IPv6 (default 127.0.0.1)
+    --port <PORT>           port to listen on (default 7777)
+    --idle-timeout <SECS>   disconnect clients silent for SECS seconds (default 60)
+    --read-timeout <MILLIS> socket read timeout in milliseconds (default 2000)
+    --log-level <LEVEL>     error, warn, info or debug (default info)
+    --max-clients <COUNT>   maximum number of clients in the chat (default 100)
+    --runtime <RUNTIME>     threads or tokio (default threads)
+    --history <COUNT>       last messages of a room shown to everyone who
+                            joins it, 0 to keep no history (default 50)
+    --chat-log <FILE>       append every room message to FILE and restore
+                            the history from it on start
+    --chat-log-size <MB>    start a new chat log when FILE grows bigger than MB,
+                            the last 5 old logs are kept as FILE.1..FILE.5
+                            (default 10)
+    --identity <FILE>       server key that clients can pin, created if FILE
+                            doesn't exist (default a new key on every start)
+    --users <FILE>          only users from FILE can join, with their passwords
+    --token <TOKEN>         password that lets any nickname join
+    -h, --help              print this help
+
+The server stops on Ctrl+C or SIGTERM after telling the clients and writing
+the chat log to disk. Exit status is 0 after a clean stop, 1 if the server
+failed and 2 if the options are invalid.";
+
+//Настройки сервера
+#[derive(Debug, Clone)]
+pub struct Config {
+    //Адрес на котором сервер слушает датаграммы
+    pub bind_address: String,
+    pub port: u16,
+    //Через сколько времени тишины клиент считается отключившимся
+    pub idle_timeout: Duration,
+    //Таймаут блокирующей операции чтения из сокета
+    pub read_timeout: Duration,
+    pub log_level: Level,
+    //Сколько клиентов одновременно может быть в чате
+    pub max_clients: usize,
+    pub runtime: Runtime,
+    //Сколько последних сообщений каждой комнаты сервер помнит и показывает вошедшим в нее
+    pub history: usize,
+    //Журнал в который записываются все сообщения комнат
+    pub chat_log: Option<PathBuf>,
+    //Размер журнала в байтах после которого начинается новый файл
+    pub chat_log_size: u64,
+    //Файл с постоянным ключом сервера. Если не указан то при каждом запуске создается новый ключ
+    // и клиенты не смогут закрепить его отпечаток
+    pub identity: Option<PathBuf>,
+    //Файл с пользователями и хешами их паролей
+    pub users: Option<PathBuf>,
+    //Общий пароль с которым может войти любой ник.
+    //Если не задан ни он ни файл пользователей то сервер пускает всех без пароля
+    pub token: Option<String>,
+    //Указан -h или --help: нужно только напечатать подсказку
+    pub help: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind_address: "127.0.0.1".to_string(),
+            port: 7777,
+            idle_timeout: Duration::from_secs(60),
+            read_timeout: Duration::from_millis(2000),
+            log_level: Level::Info,
+            max_clients: 100,
+            runtime: Runtime::Threads,
+            history: 50,
+            chat_log: None,
+            chat_log_size: 10 * 1024 * 1024,
+            identity: None,
+            users: None,
+            token: None,
+            help: false,
+        }
+    }
+}
+
+impl Config {
+    //Собирает настройки из аргументов командной строки (без имени программы).
+    //Если указан --config то сначала читается файл, а аргументы переопределяют значения из него.
+    //-h и --help распознаются только на месте ключа, поэтому `--token --help` задает токен
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Config, String> {
+        let mut options = Vec::new();
+        let mut help = false;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                help = true;
+                continue;
+            }
+            let key = match arg.strip_prefix("--") {
+                Some(key) => key.to_string(),
+                None => return Err(format!("unexpected argument {}", arg)),
+            };
+            let value = args.next().ok_or_else(|| format!("missing value for --{}", key))?;
+            options.push((key, value));
+        }
+        let mut config = Config::default();
+        //С подсказкой остальные аргументы не проверяем, их все равно никто не будет использовать
+        if help {
+            config.help = true;
+            return Ok(config);
+        }
+        if let Some((_, path)) = options.iter().find(|&(key, _)| key == "config") {
+            config.load_file(path)?;
+        }
+        for (key, value) in options {
+            if key != "config" {
+                config.set(&key, &value)?;
+            }
+        }
+        Ok(config)
+    }
+
+    //Читает настройки из файла. Каждая строка имеет вид `ключ = значение`,
+    // ключи такие же как у аргументов командной строки, строки начинающиеся с # пропускаются
+    fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("can't read config {}: {}", path, e))?;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next()
+                .ok_or_else(|| format!("{}:{}: expected `key = value`", path, number + 1))?
+                .trim();
+            self.set(key, value).map_err(|e| format!("{}:{}: {}", path, number + 1, e))?;
+        }
+        Ok(())
+    }
+
+    //Устанавливает одну настройку по ее имени
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = |_| format!("invalid value {} for {}", value, key);
+        match key {
+            "bind" => self.bind_address = value.to_string(),
+            "port" => self.port = value.parse().map_err(invalid)?,
+            "idle-timeout" => self.idle_timeout = Duration::from_secs(value.parse().map_err(invalid)?),
+            "read-timeout" => self.read_timeout = Duration::from_millis(value.parse().map_err(invalid)?),
+            "log-level" => self.log_level = value.parse()?,
+            "max-clients" => self.max_clients = value.parse().map_err(invalid)?,
+            "runtime" => self.runtime = value.parse()?,
+            "history" => self.history = value.parse().map_err(invalid)?,
+            "chat-log" => self.chat_log = Some(PathBuf::from(value)),
+            "chat-log-size" => {
+                let megabytes: u64 = value.parse().map_err(invalid)?;
+                //С нулевым размером журнал начинался бы заново на каждом сообщении
+                if megabytes == 0 {
+                    return Err(format!("{} must be at least 1 MB", key));
+                }
+                self.chat_log_size = megabytes.saturating_mul(1024 * 1024);
+            }
+            "identity" => self.identity = Some(PathBuf::from(value)),
+            "users" => self.users = Some(PathBuf::from(value)),
+            "token" => self.token = Some(value.to_string()),
+            _ => return Err(format!("unknown option {}", key)),
+        }
+        Ok(())
+    }
+}
+
+//Как сервер обрабатывает датаграммы
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    //Поток чтения из сокета и поток который по очереди рассылает пакеты всем клиентам
+    Threads,
+    //Асинхронный сервер на tokio который рассылает пакеты клиентам параллельно
+    Tokio,
+}
+
+impl FromStr for Runtime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Runtime, String> {
+        match s.to_lowercase().as_str() {
+            "threads" => Ok(Runtime::Threads),
+            "tokio" if cfg!(feature = "tokio") => Ok(Runtime::Tokio),
+            "tokio" => Err("server is built without the tokio feature".to_string()),
+            _ => Err(format!("unknown runtime {}, expected threads or tokio", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    //Файл настроек одного теста
+    fn config_file(name: &str, text: &str) -> String {
+        let path = env::temp_dir().join(format!("config_{}_{}.conf", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn parses_command_line() {
+        let config = Config::from_args(args(&["--port", "8000", "--bind", "::", "--log-level", "debug", "--history", "0"])).unwrap();
+        assert_eq!(config.port, 8000);
+        assert_eq!(config.bind_address, "::");
+        assert_eq!(config.log_level, Level::Debug);
+        assert_eq!(config.history, 0);
+        assert_eq!(config.max_clients, Config::default().max_clients);
+        assert!(!config.help);
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert!(Config::from_args(args(&["7777"])).is_err());
+        assert!(Config::from_args(args(&["--port"])).is_err());
+        assert!(Config::from_args(args(&["--port", "seven"])).is_err());
+        assert!(Config::from_args(args(&["--colour", "blue"])).is_err());
+        assert!(Config::from_args(args(&["--chat-log-size", "0"])).is_err());
+        assert_eq!(Config::from_args(args(&["--chat-log-size", "2"])).unwrap().chat_log_size, 2 * 1024 * 1024);
+    }
+
+    #[test]
+    fn help_is_a_flag_not_a_value() {
+        assert!(Config::from_args(args(&["-h"])).unwrap().help);
+        assert!(Config::from_args(args(&["--port", "8000", "--help"])).unwrap().help);
+        let config = Config::from_args(args(&["--token", "--help"])).unwrap();
+        assert!(!config.help);
+        assert_eq!(config.token.as_deref(), Some("--help"));
+    }
+
+    #[test]
+    fn parses_config_file() {
+        let path = config_file("parse", "# comment\n\nport = 8001\n  max-clients=5  \ntoken = a = b\n");
+        let config = Config::from_args(args(&["--config", &path])).unwrap();
+        assert_eq!(config.port, 8001);
+        assert_eq!(config.max_clients, 5);
+        assert_eq!(config.token.as_deref(), Some("a = b"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reports_config_file_line() {
+        let path = config_file("error", "port = 8001\nport\n");
+        let error = Config::from_args(args(&["--config", &path])).unwrap_err();
+        assert!(error.ends_with(":2: expected `key = value`"), "{}", error);
+        let _ = fs::remove_file(&path);
+        assert!(Config::from_args(args(&["--config", &path])).is_err());
+    }
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let path = config_file("precedence", "port = 8001\nhistory = 10\n");
+        //Порядок аргументов не важен: файл всегда читается первым
+        let config = Config::from_args(args(&["--port", "9000", "--config", &path])).unwrap();
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.history, 10);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,76 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use protocol::{DecodeError, Kind, SecureError};
+
+//Ошибки сервера
+#[derive(Debug)]
+pub enum ServerError {
+    //Не удалось получить адрес из настроек
+    Resolve(String),
+    //Не удалось открыть или настроить сокет
+    Socket(io::Error),
+    //Не удалось прочитать или создать файл с ключом сервера
+    Identity(PathBuf, io::Error),
+    //Не удалось прочитать файл пользователей
+    Users(PathBuf, io::Error),
+    //Не удалось открыть, прочитать или дописать журнал сообщений
+    ChatLog(PathBuf, io::Error),
+    //Датаграмма не является пакетом нашего протокола
+    Decode(SocketAddr, DecodeError),
+    //Датаграмма не прошла расшифровку: подделана, повреждена, пришла повторно или до обмена ключами
+    Secure(SocketAddr, SecureError),
+    //Пакет разобран, но данные в нем не подходят для пакета такого типа
+    InvalidPayload(SocketAddr, Kind),
+    //Не удалось отправить датаграмму клиенту
+    Send(SocketAddr, io::Error),
+    //Поток рассылки сообщений завершился и больше не принимает датаграммы
+    BroadcasterStopped,
+    //Один из потоков сервера завершился аварийно
+    ThreadPanicked,
+    //Сервер собран без поддержки запрошенной возможности
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ServerError::Resolve(ref address) => write!(f, "can't resolve address {}", address),
+            ServerError::Socket(ref e) => write!(f, "socket error: {}", e),
+            ServerError::Identity(ref path, ref e) => write!(f, "can't load server key {}: {}", path.display(), e),
+            ServerError::Users(ref path, ref e) => write!(f, "can't load users {}: {}", path.display(), e),
+            ServerError::ChatLog(ref path, ref e) => write!(f, "chat log {}: {}", path.display(), e),
+            ServerError::Decode(address, ref e) => write!(f, "can't decode packet from {}: {}", address, e),
+            ServerError::Secure(address, ref e) => write!(f, "insecure datagram from {}: {}", address, e),
+            ServerError::InvalidPayload(address, kind) => write!(f, "invalid {:?} payload from {}", kind, address),
+            ServerError::Send(address, ref e) => write!(f, "can't send to {}: {}", address, e),
+            ServerError::BroadcasterStopped => write!(f, "broadcaster thread stopped"),
+            ServerError::ThreadPanicked => write!(f, "server thread panicked"),
+            ServerError::Unsupported(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ServerError::Socket(ref e)
+            | ServerError::Identity(_, ref e)
+            | ServerError::Users(_, ref e)
+            | ServerError::ChatLog(_, ref e)
+            | ServerError::Send(_, ref e) => Some(e),
+            ServerError::Decode(_, ref e) => Some(e),
+            ServerError::Secure(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> ServerError {
+        ServerError::Socket(e)
+    }
+}
@@ -0,0 +1,44 @@
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+//События чата о которых сервер сообщает подписчикам
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    //Клиент вошел в чат
+    Connected { address: SocketAddr, nickname: String },
+    //Клиент вышел из чата, пропал по таймауту или перестал отвечать
+    Disconnected { address: SocketAddr, nickname: String },
+    //Сообщение разосланное участникам комнаты
+    Message { room: String, nickname: String, text: String },
+    //Личное сообщение одного клиента другому
+    Private { from: String, to: String, text: String },
+}
+
+//Подписчики на события сервера. Каждый подписчик получает свой канал со всеми событиями
+#[derive(Clone, Default)]
+pub struct Subscribers {
+    senders: Arc<Mutex<Vec<Sender<ServerEvent>>>>,
+}
+
+impl Subscribers {
+    pub fn new() -> Subscribers {
+        Subscribers::default()
+    }
+
+    //Создает канал в который будут приходить все следующие события
+    pub fn subscribe(&self) -> Receiver<ServerEvent> {
+        let (sx, rx) = mpsc::channel();
+        if let Ok(mut senders) = self.senders.lock() {
+            senders.push(sx);
+        }
+        rx
+    }
+
+    //Отправляет событие всем подписчикам. Подписчики которые закрыли свой канал забываются
+    pub fn publish(&self, event: ServerEvent) {
+        if let Ok(mut senders) = self.senders.lock() {
+            senders.retain(|sx| sx.send(event.clone()).is_ok());
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::chat_log::{ChatLog, Record};
+use crate::config::Config;
+use crate::error::ServerError;
+
+//Для скольких комнат сервер помнит историю. Когда комнат больше забывается та,
+// в которой дольше всех ничего не писали
+const MAX_ROOMS: usize = 256;
+
+//Последние сообщения каждой комнаты. Их получает тот кто только что вошел в комнату.
+//История комнаты переживает ее исчезновение, чтобы вернувшийся в пустую комнату увидел о чем там говорили.
+//Если в настройках указан журнал то все сообщения еще и записываются в него,
+// а при запуске сервера история восстанавливается из журнала
+pub struct History {
+    //Сколько последних сообщений помнить в каждой комнате. Ноль значит историю не хранить
+    limit: usize,
+    rooms: HashMap<String, VecDeque<Record>>,
+    log: Option<ChatLog>,
+}
+
+impl History {
+    pub fn new(limit: usize) -> History {
+        History { limit, rooms: HashMap::new(), log: None }
+    }
+
+    //История с журналом из настроек. Последние сообщения читаются из журнала.
+    //Журнал читается по одному сообщению, поэтому в памяти остается только то что поместится в историю
+    pub fn open(config: &Config) -> Result<History, ServerError> {
+        let mut history = History::new(config.history);
+        if let Some(ref path) = config.chat_log {
+            let error = |e| ServerError::ChatLog(path.clone(), e);
+            if history.limit > 0 {
+                ChatLog::scan(path, 0, u64::MAX, |record| history.remember(record)).map_err(error)?;
+            }
+            history.log = Some(ChatLog::open(path, config.chat_log_size).map_err(error)?);
+        }
+        Ok(history)
+    }
+
+    //Запоминает сообщение и записывает его в журнал
+    pub fn push(&mut self, record: Record) -> Result<(), ServerError> {
+        let written = match self.log {
+            Some(ref mut log) => log.append(&record).map_err(|e| ServerError::ChatLog(log.path().to_path_buf(), e)),
+            None => Ok(()),
+        };
+        self.remember(record);
+        written
+    }
+
+    //Сбрасывает журнал на диск
+    pub fn flush(&mut self) -> Result<(), ServerError> {
+        match self.log {
+            Some(ref mut log) => log.flush().map_err(|e| ServerError::ChatLog(log.path().to_path_buf(), e)),
+            None => Ok(()),
+        }
+    }
+
+    //Сообщения комнаты от старых к новым
+    pub fn records(&self, room: &str) -> Vec<Record> {
+        self.rooms
+            .get(room)
+            .map(|records| records.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    //Самое старое сообщение комнаты забывается если их больше limit
+    fn remember(&mut self, record: Record) {
+        if self.limit == 0 {
+            return;
+        }
+        if !self.rooms.contains_key(&record.room) && self.rooms.len() >= MAX_ROOMS {
+            self.forget_quietest();
+        }
+        let records = self.rooms.entry(record.room.clone()).or_default();
+        if records.len() >= self.limit {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    //Забывает историю комнаты последнее сообщение в которой самое старое
+    fn forget_quietest(&mut self) {
+        let quietest = self.rooms
+            .iter()
+            .min_by_key(|(_, records)| records.back().map(|record| record.timestamp))
+            .map(|(room, _)| room.clone());
+        if let Some(room) = quietest {
+            self.rooms.remove(&room);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn record(timestamp: u64, room: &str) -> Record {
+        Record { timestamp, room: room.to_string(), nickname: "alice".to_string(), text: timestamp.to_string() }
+    }
+
+    #[test]
+    fn restores_last_messages_of_each_room_from_chat_log() {
+        let dir = std::env::temp_dir().join(format!("history_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let config = Config { history: 2, chat_log: Some(dir.join("chat.log")), chat_log_size: 60, ..Config::default() };
+        let mut history = History::open(&config).unwrap();
+        //Журнал успевает повернуться, и последние сообщения лежат в разных файлах
+        for timestamp in 1..=10 {
+            history.push(record(timestamp, if timestamp % 3 == 0 { "dev" } else { "general" })).unwrap();
+        }
+        history.flush().unwrap();
+        assert!(dir.join("chat.log.1").exists());
+        let restored = History::open(&config).unwrap();
+        let timestamps = |room| restored.records(room).iter().map(|record| record.timestamp).collect::<Vec<u64>>();
+        assert_eq!(timestamps("general"), [8, 10]);
+        assert_eq!(timestamps("dev"), [6, 9]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,103 +1,645 @@
+extern crate protocol;
+
 #[macro_use]
-extern crate text_io;
+mod logger;
+mod auth;
+mod chat_server;
+mod config;
+mod error;
+mod chat_log;
+mod events;
+mod history;
+mod rooms;
+mod sessions;
+#[cfg(feature = "tokio")]
+mod tokio_server;
 
-use std::net::{UdpSocket, SocketAddr};
+use std::net::{UdpSocket, SocketAddr, ToSocketAddrs};
 use std::time::Duration;
-use std::sync::mpsc;
-use std::thread;
-
-const TIMEOUT_IN_MILLIS: u64 = 2000;
-
-//Главная точка входа в приложение
-pub fn run() {
-    //Создаем сокет
-    let socket = create_socket();
-    //Создаем односторонний канал с одним отправителем сообщений sx и множеством получателей rx
-    let (sx, rx) = mpsc::channel();
-    //Запускаем рассылку сообщений всем получателям в отдельном потоке
-    start_sender_thread(rx, socket.try_clone().unwrap());
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{self, ErrorKind};
+use std::thread::{self, JoinHandle};
+use crate::auth::Auth;
+use crate::events::Subscribers;
+use crate::history::History;
+use crate::rooms::Rooms;
+use crate::sessions::Sessions;
+use protocol::{Datagram, Identity, Kind, Packet, Reassembler, Reliable, Secure};
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+pub use crate::auth::Users;
+pub use crate::chat_log::{ChatLog, Record};
+pub use crate::chat_server::{ChatServer, ServerHandle};
+pub use crate::config::{Config, Runtime, USAGE};
+pub use crate::error::ServerError;
+pub use crate::events::ServerEvent;
+pub use crate::logger::Level;
+
+//Как часто поток рассылки проверяет нет ли отключившихся по таймауту клиентов
+// и неподтвержденных пакетов которые пора отправить повторно
+const TICK_IN_MILLIS: u64 = 100;
+//Максимальная длина ника и названия комнаты в символах
+const MAX_NAME_LENGTH: usize = 32;
+
+//Главная точка входа в приложение. Сервер работает пока процессу не придет SIGINT (Ctrl+C) или SIGTERM,
+// после чего он прощается с клиентами и останавливается. Возвращает ошибку если сервер не удалось запустить,
+// поток рассылки сообщений перестал работать или журнал не удалось дописать при остановке
+pub fn run(config: Config) -> Result<(), ServerError> {
+    let result = stop_signal()
+        .map_err(ServerError::from)
+        .and_then(|signal| ChatServer::with_config(config).spawn()?.shutdown_when(&signal));
+    match result {
+        Ok(()) => info!("server stopped"),
+        Err(ref e) => error!("{}", e),
+    }
+    result
+}
+
+//Флаг который поднимается когда процессу приходит SIGINT или SIGTERM.
+//Если сервер завис при остановке то повторный сигнал завершает процесс сразу
+fn stop_signal() -> io::Result<Arc<AtomicBool>> {
+    let signal = Arc::new(AtomicBool::new(false));
+    for &number in &[SIGINT, SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(number, 1, signal.clone())?;
+        signal_hook::flag::register(number, signal.clone())?;
+    }
+    Ok(signal)
+}
+
+//Читает датаграммы из сокета и передает их в поток рассылки сообщений пока не будет поднят флаг stop
+fn receive(socket: &UdpSocket, sx: &mpsc::Sender<(Vec<u8>, SocketAddr)>, stop: &AtomicBool) -> Result<(), ServerError> {
     loop {
-        //Читаем данные из сокета и оправляем их в поток занимающийся рассылкой сообшений клентам подключенным к серверу
-        sx.send(read_data(&socket)).unwrap();
+        let datagram = read_data(socket);
+        if stop.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        //Оправляем данные в поток занимающийся рассылкой сообшений клентам подключенным к серверу
+        if let Some(datagram) = datagram {
+            sx.send(datagram).map_err(|_| ServerError::BroadcasterStopped)?;
+        }
     }
 }
+
 //Метод для создания потока для рассылки сообщений клиентам
-fn start_sender_thread(rx: mpsc::Receiver<(Vec<u8>, SocketAddr)>, socket: UdpSocket) {
+fn start_sender_thread(rx: mpsc::Receiver<(Vec<u8>, SocketAddr)>,
+                       socket: UdpSocket,
+                       identity: Identity,
+                       auth: Auth,
+                       history: History,
+                       config: Config,
+                       subscribers: Subscribers) -> JoinHandle<Result<(), ServerError>> {
     //Запускаем новый поток. move значит что переменные переходят во владение лямбды и потока соответсвенно
     // Конкретнее наш новый поток "поглотит" переменные rx и socket
     thread::spawn(move || {
-        //Коллеция адресов подключенных к нам клиентов. Всем им мы будем разсылать наши сообщения.
-        //Вообще в реальном проекте надо бы сделать обработку отключения от нас клиента и удаления его
-        // адресса из этого массива.
-        let mut addresses = Vec::<SocketAddr>::new();
+        let mut broadcaster = Broadcaster::new(socket, identity, auth, history, &config, subscribers);
         //запускаем бесконечный цикл
         loop {
-            //Читаем данные из канала. Тут поток будет заблокирован до тех пор пока не прийдут новые данные
-            let (bytes, source) = rx.recv().unwrap();
-            // Если такого адреса нет в нашем массиве то добавляем его туда
-            if !addresses.contains(&source) {
-                println!(" {} connected to server", source);
-                addresses.push(source.clone());
+            //Читаем данные из канала. Ждем не дольше TICK_IN_MILLIS чтобы
+            // даже при отсутствии сообщений регулярно проверять молчащих клиентов
+            match rx.recv_timeout(Duration::from_millis(TICK_IN_MILLIS)) {
+                Ok((bytes, source)) => broadcaster.handle_datagram(&bytes, source),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                //Поток чтения завершился и больше данных не будет
+                Err(mpsc::RecvTimeoutError::Disconnected) => return broadcaster.shutdown(),
             }
-            //Декодируем UTF8 строку из массива байт
-            let result = String::from_utf8(bytes)
-                .expect("can't parse to String")
-                .trim()
-                .to_string();
-            println!("received {} from {}", result, source);
-            //Создаем массив байт которые собираемся отправить всем нашим клиентам
-            let message = format!("FROM: {} MESSAGE: {}", source, result);
-            let data_to_send = message.as_bytes();
-            //Проходим по коллецкии адресов и отправляем данные каждому.
-            addresses
-                .iter()
-                .for_each(|s| {
-                    //Операция записи в UDP сокет неблокирующая поэтому
-                    //здесь метод не будет ждать пока сообщение прийдет к получателю и выполниться почти
-                    //мнгновенно
-                    socket
-                        .send_to(data_to_send, s)
-                        .expect(format!("can't send to {}", source).as_str());
-                });
-        }
-    });
+            //Повторно отправляем пакеты которые клиенты не подтвердили
+            // и выбрасываем пакеты недостающие фрагменты которых так и не пришли
+            broadcaster.retransmit();
+            //Удаляем клиентов которые слишком долго ничего не присылали
+            broadcaster.evict_idle();
+        }
+    })
+}
+
+//Состояние потока рассылки сообщений.
+//Сокет может быть любым Datagram, асинхронный сервер подставляет сюда очередь исходящих датаграмм.
+//Все что уходит в сокет шифруется ключами которые клиент получил при обмене ключами
+struct Broadcaster<S: Datagram> {
+    socket: Secure<S>,
+    //Таблица сессий подключенных к нам клиентов. Всем им мы будем разсылать наши сообщения.
+    sessions: Sessions,
+    //Комнаты и их участники. Сообщение получают только участники комнаты в которую оно написано
+    rooms: Rooms,
+    //Последние сообщения каждой комнаты для тех кто в нее входит
+    history: History,
+    //Подтверждения, повторная отправка и отбрасывание дубликатов
+    reliable: Reliable,
+    //Сборка больших пакетов из фрагментов
+    reassembler: Reassembler,
+    //Проверка паролей и блокировка тех кто их подбирает
+    auth: Auth,
+    //Через сколько времени тишины клиент считается отключившимся
+    idle_timeout: Duration,
+    //Сколько клиентов одновременно может быть в чате
+    max_clients: usize,
+    //Кому сообщать о событиях чата
+    subscribers: Subscribers,
 }
 
-//Создает сокет на основе данных введенных пользователем
-fn create_socket() -> UdpSocket {
-    println!("Enter port to listen");
-    //Считываем порт который будет слушать наш сервер и создаем на его основе адрес сервера
-    let local_port: String = read!("{}\n");
-    let local_address = format!("127.0.0.1:{}", local_port.trim());
-    println!("server address {}", &local_address);
+impl<S: Datagram> Broadcaster<S> {
+    fn new(socket: S,
+           identity: Identity,
+           auth: Auth,
+           history: History,
+           config: &Config,
+           subscribers: Subscribers) -> Broadcaster<S> {
+        Broadcaster {
+            socket: Secure::new(socket, identity),
+            sessions: Sessions::new(),
+            rooms: Rooms::new(),
+            history,
+            reliable: Reliable::new(),
+            reassembler: Reassembler::new(),
+            auth,
+            idle_timeout: config.idle_timeout,
+            max_clients: config.max_clients,
+            subscribers,
+        }
+    }
+
+    //Обрабатывает датаграмму пришедшую от клиента. Ошибки только записываются в лог,
+    // чтобы один испорченный пакет или недоступный клиент не остановил весь чат
+    fn handle_datagram(&mut self, bytes: &[u8], source: SocketAddr) {
+        match self.process(bytes, source) {
+            Ok(()) => {}
+            //Клиенту приславшему неверные данные сообщаем что пакет не принят
+            Err(ServerError::InvalidPayload(address, kind)) => {
+                warn!("{}", ServerError::InvalidPayload(address, kind));
+                let reason = format!("invalid {:?} packet", kind);
+                if self.sessions.id(&address).is_some() {
+                    self.notify_client(address, &reason);
+                } else {
+                    self.reject(address, &reason);
+                }
+            }
+            //Такие датаграммы шлет кто угодно, а после того как сервер забыл ключи клиента
+            // они идут потоком, поэтому в обычном логе они только мешали бы
+            Err(e @ ServerError::Secure(..)) => debug!("{}", e),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    //Разбирает датаграмму и выполняет то что просит клиент
+    fn process(&mut self, bytes: &[u8], source: SocketAddr) -> Result<(), ServerError> {
+        //Расшифровываем датаграмму. На предложение обменяться ключами уже ответил сам сокет
+        let bytes = match self.socket.open(bytes, source) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(ServerError::Secure(source, e)),
+        };
+        //Разбираем пакет из массива байт. Пакеты которые не удалось разобрать пропускаем.
+        //Фрагменты большого пакета копим пока не придут все
+        let packet = match protocol::decode(&bytes).and_then(|p| self.reassembler.accept(source, p)) {
+            Ok(Some(packet)) => packet,
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(ServerError::Decode(source, e)),
+        };
+        //Обновляем время последней активности клиента
+        let joined = self.sessions.touch(&source);
+        //Подтверждаем получение пакета. Подтверждения от клиентов и повторно пришедшие пакеты
+        // дальше не обрабатываем.
+        //От тех кто не вошел в чат номера пакетов не запоминаем, иначе они могли бы занимать память не зная пароля
+        let fresh = if joined || matches!(packet.kind, Kind::Join | Kind::Leave | Kind::Ack) {
+            self.reliable.receive(&self.socket, source, &packet)
+        } else {
+            self.reliable.acknowledge(&self.socket, source, &packet).map(|_| false)
+        };
+        let fresh = fresh.map_err(|e| ServerError::Send(source, e))?;
+        match packet.kind {
+            //Вход обрабатываем даже если такой пакет уже приходил: клиент мог перезапуститься
+            // на том же адресе и начать нумерацию пакетов заново
+            Kind::Join => self.join(source, &packet)?,
+            //Клиент сообщил что отключается. Убираем его из рассылки и оповещаем остальных
+            Kind::Leave => {
+                if fresh {
+                    if let Some(nickname) = self.sessions.remove(&source) {
+                        info!("{} ({}) left server", source, nickname);
+                        self.disconnected(&source, &nickname);
+                    }
+                }
+                self.forget(&source);
+                //Подтверждение выхода уже отправлено, ключи клиента больше не нужны
+                self.socket.forget(&source);
+            }
+            //Все кроме входа, выхода и подтверждений принимаем только от клиентов которые вошли в чат,
+            // иначе обмена ключами хватило бы чтобы писать в чат не зная пароля
+            Kind::Message | Kind::Private | Kind::RoomJoin | Kind::RoomLeave | Kind::RoomList if !joined => {
+                self.reject(source, "join the chat first")
+            }
+            //Клиент заметил пропуск в нумерации и просит повторить пакеты не дожидаясь таймаута
+            Kind::Resend if joined => {
+                for sequence in packet.sequences() {
+                    self.reliable
+                        .resend(&self.socket, source, sequence)
+                        .map_err(|e| ServerError::Send(source, e))?;
+                }
+            }
+            //Отвечаем на проверку связи теми же данными. Тем кто не вошел в чат не отвечаем,
+            // так клиент сервер которого перезапустился поймет что его сессии больше нет
+            Kind::Ping if joined => self.send(source, Kind::Pong, protocol::SERVER_ID, packet.payload.clone()),
+            Kind::Message if fresh => self.message(source, &packet)?,
+            Kind::Private if fresh => self.private_message(source, &packet)?,
+            Kind::RoomJoin if fresh => self.join_room(source, &packet)?,
+            Kind::RoomLeave if fresh => self.leave_room(source, &packet)?,
+            Kind::RoomList if fresh => {
+                let names = self.rooms.names();
+                let names: Vec<&str> = names.iter().map(String::as_str).collect();
+                self.send(source, Kind::RoomList, protocol::SERVER_ID, protocol::encode_fields(&names));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    //Обрабатывает вход клиента в чат: проверяет ник и пароль и отвечает согласием или отказом
+    fn join(&mut self, source: SocketAddr, packet: &Packet) -> Result<(), ServerError> {
+        //Этот вход уже обработан, а пакет пришел повторно
+        if self.sessions.join_timestamp(&source) == Some(packet.timestamp) {
+            return Ok(());
+        }
+        //Клиент присылает ник и пароль, а после обрыва связи еще и токен прошлой сессии.
+        //Если сервер пускает всех то пароль пустой
+        let (nickname, password, token) = match packet.fields().as_deref() {
+            Some([nickname, password]) => (nickname.trim().to_string(), password.clone(), None),
+            Some([nickname, password, token]) => (nickname.trim().to_string(), password.clone(), Some(token.clone())),
+            _ => return Err(ServerError::InvalidPayload(source, packet.kind)),
+        };
+        if let Some(token) = token {
+            if self.resume(source, packet, &token)? {
+                return Ok(());
+            }
+        }
+        //Клиент перезапустился на том же адресе. Забываем все что знали о его прошлой сессии
+        if let Some(nickname) = self.sessions.remove(&source) {
+            info!("{} ({}) rejoined server", source, nickname);
+            self.disconnected(&source, &nickname);
+        }
+        self.forget(&source);
+        //Заново запоминаем номер пакета входа чтобы не принять его повторно как новый
+        self.reliable
+            .receive(&self.socket, source, packet)
+            .map_err(|e| ServerError::Send(source, e))?;
+        //Пароль проверяем первым, чтобы не подсказывать тем кто его не знает кто сейчас в чате
+        let checked = self.auth
+            .check(source.ip(), &nickname, &password)
+            .and_then(|_| self.check_nickname(&nickname));
+        if let Err(reason) = checked {
+            info!("{} rejected: {}", source, reason);
+            self.reject(source, &reason);
+            return Ok(());
+        }
+        info!("{} ({}) connected to server", source, nickname);
+        let id = self.sessions.join(source, nickname.clone(), packet.timestamp);
+        self.subscribers.publish(ServerEvent::Connected { address: source, nickname: nickname.clone() });
+        self.accept(source, id);
+        //Сразу после входа клиент попадает в комнату по умолчанию
+        self.rooms.join(protocol::DEFAULT_ROOM, source);
+        self.send_membership(source);
+        self.send_history(source, protocol::DEFAULT_ROOM);
+        self.notify_room(protocol::DEFAULT_ROOM, &format!("{} joined the chat", nickname));
+        Ok(())
+    }
+
+    //Восстанавливает сессию по токену который клиент получил при входе: ник и комнаты остаются прежними,
+    // а пароль не спрашивается. Возвращает false если сервер такой сессии не помнит,
+    // например после перезапуска, тогда клиент входит как в первый раз
+    fn resume(&mut self, source: SocketAddr, packet: &Packet, token: &str) -> Result<bool, ServerError> {
+        //Заблокированному адресу откажет обычный вход
+        if self.auth.is_banned(&source.ip()) {
+            return Ok(false);
+        }
+        let (nickname, rooms, suspended) = if let Some(old) = self.sessions.find_token(token) {
+            //Сервер еще не заметил обрыв связи. Молча переносим сессию на новый адрес
+            let rooms = self.rooms.leave_all(&old);
+            self.forget(&old);
+            if old != source {
+                self.socket.forget(&old);
+            }
+            (self.sessions.remove(&old).unwrap_or_default(), rooms, false)
+        } else if let Some((nickname, rooms)) = self.sessions.take_suspended(token) {
+            //Пока клиента не было его ник могли занять. Тогда причину отказа сообщит обычный вход
+            if self.check_nickname(&nickname).is_err() {
+                return Ok(false);
+            }
+            (nickname, rooms, true)
+        } else {
+            return Ok(false);
+        };
+        if let Some(previous) = self.sessions.remove(&source) {
+            info!("{} ({}) rejoined server", source, previous);
+            self.disconnected(&source, &previous);
+        }
+        self.forget(&source);
+        self.reliable
+            .receive(&self.socket, source, packet)
+            .map_err(|e| ServerError::Send(source, e))?;
+        info!("{} ({}) resumed session", source, nickname);
+        let id = self.sessions.join(source, nickname.clone(), packet.timestamp);
+        self.accept(source, id);
+        //Историю комнат клиент уже видел, поэтому ее не повторяем
+        for room in &rooms {
+            self.rooms.join(room, source);
+        }
+        self.send_membership(source);
+        if suspended {
+            self.subscribers.publish(ServerEvent::Connected { address: source, nickname: nickname.clone() });
+            for room in &rooms {
+                self.notify_room(room, &format!("{} reconnected", nickname));
+            }
+        }
+        Ok(true)
+    }
+
+    //Сообщает клиенту что он в чате: его идентификатор и токен для восстановления сессии
+    fn accept(&mut self, address: SocketAddr, id: u32) {
+        let token = self.sessions.token(&address).unwrap_or_default().as_bytes().to_vec();
+        self.send(address, Kind::Accepted, id, token);
+    }
+
+    //Проверяет что под этим ником можно войти в чат
+    fn check_nickname(&self, nickname: &str) -> Result<(), String> {
+        check_name("nickname", nickname)?;
+        if self.sessions.len() >= self.max_clients {
+            return Err("server is full".to_string());
+        }
+        if self.sessions.is_taken(nickname) {
+            return Err(format!("nickname {} is already taken", nickname));
+        }
+        Ok(())
+    }
+
+    //Рассылает сообщение клиента всем участникам комнаты подписав его ником автора
+    fn message(&mut self, source: SocketAddr, packet: &Packet) -> Result<(), ServerError> {
+        //Клиент присылает название комнаты и текст сообщения
+        let (room, result) = match packet.fields().as_deref() {
+            Some([room, text]) => (room.clone(), text.trim().to_string()),
+            _ => return Err(ServerError::InvalidPayload(source, packet.kind)),
+        };
+        if !self.rooms.is_member(&room, &source) {
+            self.notify_client(source, &format!("you are not in #{}", room));
+            return Ok(());
+        }
+        let nickname = self.sessions.nickname(&source).unwrap_or_default().to_string();
+        debug!("received {} from {} ({}) in #{}", result, source, nickname, room);
+        let sender = self.sessions.id(&source).unwrap_or(protocol::SERVER_ID);
+        let payload = protocol::encode_fields(&[&room, &nickname, &result]);
+        self.broadcast_room(&room, Kind::Message, sender, payload);
+        //Сообщение уже разослано, поэтому если журнал не удалось дописать то об этом только пишем в лог
+        let record = Record {
+            timestamp: protocol::now_millis(),
+            room: room.clone(),
+            nickname: nickname.clone(),
+            text: result.clone(),
+        };
+        if let Err(e) = self.history.push(record) {
+            warn!("{}", e);
+        }
+        self.subscribers.publish(ServerEvent::Message { room, nickname, text: result });
+        Ok(())
+    }
+
+    //Пересылает личное сообщение только получателю и копию автору
+    fn private_message(&mut self, source: SocketAddr, packet: &Packet) -> Result<(), ServerError> {
+        //Клиент присылает ник получателя и текст сообщения
+        let (target, text) = match packet.fields().as_deref() {
+            Some([target, text]) => (target.clone(), text.trim().to_string()),
+            _ => return Err(ServerError::InvalidPayload(source, packet.kind)),
+        };
+        let (address, target) = match self.sessions.find(&target) {
+            Some((address, nickname)) => (address, nickname.to_string()),
+            None => {
+                self.notify_client(source, &format!("there is no one named {}", target));
+                return Ok(());
+            }
+        };
+        let nickname = self.sessions.nickname(&source).unwrap_or_default().to_string();
+        debug!("private {} from {} to {}", text, nickname, target);
+        let sender = self.sessions.id(&source).unwrap_or(protocol::SERVER_ID);
+        let payload = protocol::encode_fields(&[&nickname, &target, &text]);
+        self.send(address, Kind::Private, sender, payload.clone());
+        //Автор получает копию чтобы увидеть свое сообщение в чате. Если он написал сам себе то копия не нужна
+        if address != source {
+            self.send(source, Kind::Private, sender, payload);
+        }
+        self.subscribers.publish(ServerEvent::Private { from: nickname, to: target, text });
+        Ok(())
+    }
+
+    //Добавляет клиента в комнату. Если такой комнаты нет то она создается
+    fn join_room(&mut self, source: SocketAddr, packet: &Packet) -> Result<(), ServerError> {
+        let room = text(source, packet)?;
+        if let Err(reason) = check_name("room name", &room) {
+            self.notify_client(source, &reason);
+            return Ok(());
+        }
+        let joined = self.rooms.join(&room, source);
+        self.send_membership(source);
+        if joined {
+            self.send_history(source, &room);
+            let nickname = self.sessions.nickname(&source).unwrap_or_default().to_string();
+            self.notify_room(&room, &format!("{} joined #{}", nickname, room));
+        }
+        Ok(())
+    }
+
+    //Убирает клиента из комнаты
+    fn leave_room(&mut self, source: SocketAddr, packet: &Packet) -> Result<(), ServerError> {
+        let room = text(source, packet)?;
+        if self.rooms.leave(&room, &source) {
+            let nickname = self.sessions.nickname(&source).unwrap_or_default().to_string();
+            self.notify_room(&room, &format!("{} left #{}", nickname, room));
+        }
+        self.send_membership(source);
+        Ok(())
+    }
+
+    //Сообщает клиенту в каких комнатах он состоит
+    fn send_membership(&mut self, address: SocketAddr) {
+        let rooms = self.rooms.rooms_of(&address);
+        let rooms: Vec<&str> = rooms.iter().map(String::as_str).collect();
+        self.send(address, Kind::Membership, protocol::SERVER_ID, protocol::encode_fields(&rooms));
+    }
+
+    //Отправляет клиенту который только что вошел в комнату ее последние сообщения
+    fn send_history(&mut self, address: SocketAddr, room: &str) {
+        for record in self.history.records(room) {
+            let timestamp = record.timestamp.to_string();
+            let payload = protocol::encode_fields(&[room, &record.nickname, &record.text, &timestamp]);
+            self.send(address, Kind::History, protocol::SERVER_ID, payload);
+        }
+    }
+
+    //Отказывает клиенту. Отказ не требует подтверждения так как у клиента нет сессии
+    fn reject(&mut self, address: SocketAddr, reason: &str) {
+        self.send(address, Kind::Rejected, protocol::SERVER_ID, reason.as_bytes().to_vec());
+    }
+
+    //Повторно отправляет неподтвержденные пакеты. Клиентов которые так и не ответили
+    // считаем отключившимися
+    fn retransmit(&mut self) {
+        self.reassembler.expire();
+        for address in self.reliable.retransmit(&self.socket) {
+            self.socket.forget(&address);
+            self.suspend(&address, "is unreachable");
+        }
+    }
+
+    //Отключает клиентов которые молчали дольше idle_timeout и оповещает об этом остальных
+    fn evict_idle(&mut self) {
+        for address in self.sessions.idle(self.idle_timeout) {
+            self.suspend(&address, "timed out");
+        }
+        //Ключи тех кто давно молчит тоже забываем, в том числе тех кто так и не вошел в чат.
+        //Вместе с ключами забываем их пакеты, иначе каждый брошенный адрес занимал бы память до остановки сервера
+        for address in self.socket.expire(self.idle_timeout) {
+            self.forget(&address);
+        }
+        self.auth.expire();
+    }
+
+    //Отключает клиента который пропал не попрощавшись. Его сессию сервер еще какое то время помнит,
+    // чтобы клиент мог вернуться в свои комнаты когда связь восстановится
+    fn suspend(&mut self, address: &SocketAddr, reason: &str) {
+        let rooms = self.rooms.rooms_of(address);
+        if let Some(nickname) = self.sessions.suspend(address, rooms) {
+            info!("{} ({}) {}", address, nickname, reason);
+            self.disconnected(address, &nickname);
+        }
+        self.forget(address);
+    }
+
+    //Сообщает всем клиентам что сервер останавливается и сбрасывает журнал на диск.
+    //Подтверждений не ждем: клиент до которого оповещение не дошло сам заметит что сервер не отвечает
+    fn shutdown(&mut self) -> Result<(), ServerError> {
+        info!("server is shutting down");
+        for address in self.sessions.addresses() {
+            self.notify_client(address, "server is shutting down");
+        }
+        self.history.flush()
+    }
+
+    //Забывает недоставленные и недособранные пакеты отключившегося клиента.
+    //Из комнат он к этому времени уже вышел через disconnected, но если что то осталось то убираем молча
+    fn forget(&mut self, address: &SocketAddr) {
+        self.reliable.forget(address);
+        self.reassembler.forget(address);
+        self.rooms.leave_all(address);
+    }
+
+    //Убирает отключившегося клиента из всех комнат и оповещает об этом их участников
+    fn disconnected(&mut self, address: &SocketAddr, nickname: &str) {
+        self.subscribers.publish(ServerEvent::Disconnected { address: *address, nickname: nickname.to_string() });
+        for room in self.rooms.leave_all(address) {
+            self.notify_room(&room, &format!("{} disconnected", nickname));
+        }
+    }
+
+    //Отправляет участникам комнаты служебное оповещение от имени сервера
+    fn notify_room(&mut self, room: &str, text: &str) {
+        self.broadcast_room(room, Kind::Notice, protocol::SERVER_ID, protocol::encode_fields(&[room, text]));
+    }
+
+    //Отправляет одному клиенту служебное оповещение не относящееся к какой либо комнате
+    fn notify_client(&mut self, address: SocketAddr, text: &str) {
+        self.send(address, Kind::Notice, protocol::SERVER_ID, protocol::encode_fields(&["", text]));
+    }
+
+    //Отправляет пакет всем участникам комнаты
+    fn broadcast_room(&mut self, room: &str, kind: Kind, sender: u32, payload: Vec<u8>) {
+        //Проходим по коллецкии адресов и отправляем данные каждому.
+        for address in self.rooms.members(room) {
+            self.send(address, kind, sender, payload.clone());
+        }
+    }
+
+    //Отправляет пакет одному клиенту. Неудачная отправка не прерывает работу сервера:
+    // она записывается в лог, а пакет требующий подтверждения будет отправлен повторно
+    fn send(&mut self, address: SocketAddr, kind: Kind, sender: u32, payload: Vec<u8>) {
+        //У каждого клиента своя нумерация пакетов требующих подтверждения
+        let sequence = if kind.is_reliable() {
+            match self.sessions.next_sequence(&address) {
+                Some(sequence) => sequence,
+                None => return,
+            }
+        } else {
+            0
+        };
+        let packet = Packet::new(kind, sender, sequence, payload);
+        //Операция записи в UDP сокет неблокирующая поэтому
+        //здесь метод не будет ждать пока сообщение прийдет к получателю и выполниться почти
+        //мнгновенно. Пакет будет отправляться повторно пока клиент не подтвердит его получение
+        if let Err(e) = self.reliable.send(&self.socket, address, &packet) {
+            let failures = self.sessions.record_send_failure(&address).unwrap_or(1);
+            warn!("{} (failure {} for this client)", ServerError::Send(address, e), failures);
+        }
+    }
+}
+
+//Текст пакета без пробелов по краям. Ошибка если текст не в UTF-8
+fn text(source: SocketAddr, packet: &Packet) -> Result<String, ServerError> {
+    packet.text()
+        .map(|text| text.trim().to_string())
+        .map_err(|_| ServerError::InvalidPayload(source, packet.kind))
+}
+
+//Проверяет что ник или название комнаты не пустые, не слишком длинные и без пробелов
+fn check_name(what: &str, name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(format!("{} is empty", what));
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(format!("{} is longer than {} characters", what, MAX_NAME_LENGTH));
+    }
+    if name.chars().any(char::is_whitespace) {
+        return Err(format!("{} can't contain spaces", what));
+    }
+    Ok(())
+}
+
+//Создает сокет на адресе и порту из настроек. Адрес может быть IPv4 или IPv6,
+// например 0.0.0.0 чтобы слушать все IPv4 интерфейсы или :: чтобы слушать и IPv4 и IPv6
+fn create_socket(config: &Config) -> Result<UdpSocket, ServerError> {
+    let local_address = (config.bind_address.as_str(), config.port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next())
+        .ok_or_else(|| ServerError::Resolve(config.bind_address.clone()))?;
     //Создаем UDP сокет прослущивающий этот адрес
-    let socket = UdpSocket::bind(&local_address.trim())
-        .expect(format!("can't bind socket to {}", &local_address).as_str());
+    let socket = protocol::bind(local_address)?;
+    info!("server address {}", socket.local_addr()?);
     //Устанавливаем таймаут для операции чтения. Операция чтения блокирующая и она заблокирует поток
     //до тех пор пока не прийдут новые данные или не наступит таймаут
-    socket.set_read_timeout(Some(Duration::from_millis(TIMEOUT_IN_MILLIS)))
-        .expect("can't set time out to read");
+    socket.set_read_timeout(Some(config.read_timeout))?;
     //Возвращаем из метода созданные сокет
-    socket
+    Ok(socket)
 }
 
-//Читает данные из сокета и возвшает их вместе с адресом оправителя
-fn read_data(socket: &UdpSocket) -> (Vec<u8>, SocketAddr) {
+//Читает постоянный ключ сервера из файла указанного в настройках или создает новый ключ
+fn load_identity(config: &Config) -> Result<Identity, ServerError> {
+    let identity = match config.identity {
+        Some(ref path) => Identity::load_or_generate(path).map_err(|e| ServerError::Identity(path.clone(), e))?,
+        None => Identity::generate(),
+    };
+    //Этот отпечаток клиенты сверяют чтобы убедиться что говорят с настоящим сервером
+    info!("server fingerprint {}", identity.fingerprint());
+    Ok(identity)
+}
+
+//Читает данные из сокета и возвшает их вместе с адресом оправителя.
+//Если за время таймаута чтения ничего не пришло то возвращает None
+fn read_data(socket: &UdpSocket) -> Option<(Vec<u8>, SocketAddr)> {
     //Буфер куда будем считывать данные
     let mut buf = [0u8; 4096];
-    //Запускает цикл который будет выполняться до тех пор пока не будут считаны валидные данные
-    loop {
-        match socket.recv_from(&mut buf) {
-            //Получаем количество считанных байт и адрес отправителя
-            Ok((count, address)) => {
-                //Делем срез массива от его начала до количеств считанных байт и преборазуем его в вектор байт
-                return (buf[..count].into(), address);
-            }
-            //Если произошёл таймаут или другая ошибка то переходим к следующей итерации цикла
-            Err(e) => {
-                println!("Error {}", e);
-                continue;
-            }
-        };
+    match socket.recv_from(&mut buf) {
+        //Получаем количество считанных байт и адрес отправителя.
+        //Делем срез массива от его начала до количеств считанных байт и преборазуем его в вектор байт
+        Ok((count, address)) => Some((buf[..count].into(), address)),
+        //Таймаут это нормально, данных просто нет
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => None,
+        Err(e) => {
+            warn!("can't read from socket: {}", e);
+            None
+        }
     }
-}
\ No newline at end of file
+}
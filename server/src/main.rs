@@ -1,5 +1,80 @@
 extern crate server;
 
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process;
+
 fn main(){
-   server::run()
-}
\ No newline at end of file
+   let args: Vec<String> = env::args().skip(1).collect();
+   if args.first().map(String::as_str) == Some("add-user") {
+      add_user(&args[1..]);
+      return;
+   }
+   if args.first().map(String::as_str) == Some("log") {
+      print_log(&args[1..]);
+      return;
+   }
+   let config = server::Config::from_args(args).unwrap_or_else(|e| {
+      eprintln!("{}\n\n{}", e, server::USAGE);
+      process::exit(2)
+   });
+   if config.help {
+      println!("{}", server::USAGE);
+      return;
+   }
+   //Причину ошибки сервер уже записал в лог
+   if server::run(config).is_err() {
+      process::exit(1)
+   }
+}
+
+//Добавляет пользователя в файл пользователей. Пароль читается из стандартного ввода,
+// чтобы он не остался в истории команд и не был виден в списке процессов
+fn add_user(args: &[String]) {
+   if args.len() != 2 {
+      eprintln!("{}", server::USAGE);
+      process::exit(2);
+   }
+   eprintln!("password for {}:", args[1]);
+   let mut password = String::new();
+   if let Err(e) = io::stdin().read_line(&mut password) {
+      eprintln!("can't read password: {}", e);
+      process::exit(1);
+   }
+   let password = password.trim_end_matches(['\r', '\n']);
+   if password.is_empty() {
+      eprintln!("password is empty");
+      process::exit(2);
+   }
+   if let Err(e) = server::Users::add(Path::new(&args[0]), args[1].trim(), password) {
+      eprintln!("can't add user to {}: {}", args[0], e);
+      process::exit(1);
+   }
+}
+
+//Печатает сообщения из журнала отправленные в заданный промежуток времени
+fn print_log(args: &[String]) {
+   if args.is_empty() || args.len() > 3 {
+      eprintln!("{}", server::USAGE);
+      process::exit(2);
+   }
+   //Время в секундах переводим в миллисекунды, в которых оно хранится в журнале
+   let time = |index: usize, default: u64| match args.get(index) {
+      Some(seconds) => seconds.parse::<u64>().map(|seconds| seconds.saturating_mul(1000)).unwrap_or_else(|_| {
+         eprintln!("invalid time {}, expected seconds since the UNIX epoch", seconds);
+         process::exit(2)
+      }),
+      None => default,
+   };
+   let (from, to) = (time(1, 0), time(2, u64::MAX));
+   match server::ChatLog::query(Path::new(&args[0]), from, to) {
+      Ok(records) => for record in records {
+         println!("{}", record);
+      },
+      Err(e) => {
+         eprintln!("can't read chat log {}: {}", args[0], e);
+         process::exit(1);
+      }
+   }
+}
@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+
+//Комнаты чата и их участники.
+//Комната появляется когда в нее входит первый участник и исчезает когда выходит последний
+#[derive(Default)]
+pub struct Rooms {
+    rooms: BTreeMap<String, BTreeSet<SocketAddr>>,
+}
+
+impl Rooms {
+    pub fn new() -> Rooms {
+        Rooms::default()
+    }
+
+    //Добавляет клиента в комнату. Возвращает false если он уже был в ней
+    pub fn join(&mut self, room: &str, address: SocketAddr) -> bool {
+        self.rooms
+            .entry(room.to_string())
+            .or_default()
+            .insert(address)
+    }
+
+    //Убирает клиента из комнаты. Возвращает false если его там не было
+    pub fn leave(&mut self, room: &str, address: &SocketAddr) -> bool {
+        let left = match self.rooms.get_mut(room) {
+            Some(members) => members.remove(address),
+            None => false,
+        };
+        self.rooms.retain(|_, members| !members.is_empty());
+        left
+    }
+
+    //Убирает клиента из всех комнат и возвращает названия комнат в которых он был
+    pub fn leave_all(&mut self, address: &SocketAddr) -> Vec<String> {
+        let rooms = self.rooms_of(address);
+        for room in &rooms {
+            self.leave(room, address);
+        }
+        rooms
+    }
+
+    //Состоит ли клиент в комнате
+    pub fn is_member(&self, room: &str, address: &SocketAddr) -> bool {
+        self.rooms
+            .get(room)
+            .map(|members| members.contains(address))
+            .unwrap_or(false)
+    }
+
+    //Адреса всех участников комнаты
+    pub fn members(&self, room: &str) -> Vec<SocketAddr> {
+        self.rooms
+            .get(room)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    //Названия комнат в которых состоит клиент
+    pub fn rooms_of(&self, address: &SocketAddr) -> Vec<String> {
+        self.rooms
+            .iter()
+            .filter(|&(_, members)| members.contains(address))
+            .map(|(room, _)| room.clone())
+            .collect()
+    }
+
+    //Названия всех комнат
+    pub fn names(&self) -> Vec<String> {
+        self.rooms.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn joins_and_leaves_rooms() {
+        let mut rooms = Rooms::new();
+        assert!(rooms.join("general", address(1)));
+        assert!(!rooms.join("general", address(1)));
+        assert!(rooms.join("general", address(2)));
+        assert_eq!(rooms.members("general"), [address(1), address(2)]);
+        assert!(rooms.is_member("general", &address(1)));
+        assert!(rooms.leave("general", &address(1)));
+        assert!(!rooms.leave("general", &address(1)));
+        assert!(!rooms.leave("missing", &address(1)));
+        assert!(!rooms.is_member("general", &address(1)));
+    }
+
+    #[test]
+    fn removes_empty_rooms() {
+        let mut rooms = Rooms::new();
+        rooms.join("rust", address(1));
+        rooms.join("general", address(1));
+        assert_eq!(rooms.names(), ["general", "rust"]);
+        rooms.leave("rust", &address(1));
+        assert_eq!(rooms.names(), ["general"]);
+        assert!(rooms.members("rust").is_empty());
+    }
+
+    #[test]
+    fn leave_all_returns_rooms_of_client() {
+        let mut rooms = Rooms::new();
+        rooms.join("general", address(1));
+        rooms.join("rust", address(1));
+        rooms.join("general", address(2));
+        assert_eq!(rooms.rooms_of(&address(1)), ["general", "rust"]);
+        assert_eq!(rooms.leave_all(&address(1)), ["general", "rust"]);
+        assert!(rooms.rooms_of(&address(1)).is_empty());
+        //Комнату в которой остались другие участники оставляем, опустевшую убираем
+        assert_eq!(rooms.names(), ["general"]);
+        assert_eq!(rooms.members("general"), [address(2)]);
+        assert!(rooms.leave_all(&address(1)).is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use rand_core::{OsRng, RngCore};
+
+//Сколько секунд сервер помнит сессию клиента который пропал не попрощавшись.
+//За это время клиент может переподключиться и вернуться под своим ником в свои комнаты
+const RESUME_WINDOW_IN_SECS: u64 = 300;
+//Длина токена сессии в байтах
+const TOKEN_SIZE: usize = 16;
+
+//Сессия подключенного к серверу клиента
+struct Session {
+    //Идентификатор клиента который сервер указывает как отправителя его сообщений
+    id: u32,
+    //Ник под которым клиент вошел в чат
+    nickname: String,
+    //Время из пакета Join которым клиент вошел в чат. По нему повторно дошедший Join
+    // отличается от нового входа с того же адреса
+    join_timestamp: u64,
+    //Время когда от клиента в последний раз пришли данные
+    last_seen: Instant,
+    //Номер последнего отправленного этому клиенту пакета
+    sequence: u32,
+    //Сколько раз не удалось отправить клиенту датаграмму
+    send_failures: u32,
+    //Секрет который клиент получает при входе. По нему он восстанавливает сессию после обрыва связи
+    token: String,
+}
+
+//Сессия клиента связь с которым оборвалась
+struct Suspended {
+    nickname: String,
+    //Комнаты в которых состоял клиент
+    rooms: Vec<String>,
+    since: Instant,
+}
+
+//Таблица сессий всех подключенных к серверу клиентов
+pub struct Sessions {
+    sessions: HashMap<SocketAddr, Session>,
+    //Сессии пропавших клиентов по их токенам
+    suspended: HashMap<String, Suspended>,
+    //Идентификатор который получит следующий подключившийся клиент
+    next_id: u32,
+}
+
+impl Sessions {
+    pub fn new() -> Sessions {
+        Sessions {
+            sessions: HashMap::new(),
+            suspended: HashMap::new(),
+            //0 зарезервирован за самим сервером
+            next_id: protocol::SERVER_ID + 1,
+        }
+    }
+
+    //Создает сессию для клиента вошедшего в чат под ником nickname и возвращает его идентификатор.
+    //Если у клиента с этим адресом уже была сессия то она заменяется новой
+    pub fn join(&mut self, address: SocketAddr, nickname: String, join_timestamp: u64) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut token = [0u8; TOKEN_SIZE];
+        OsRng.fill_bytes(&mut token);
+        self.sessions.insert(address, Session {
+            id,
+            nickname,
+            join_timestamp,
+            last_seen: Instant::now(),
+            sequence: 0,
+            send_failures: 0,
+            token: token.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        });
+        id
+    }
+
+    //Обновляет время последней активности клиента.
+    //Возвращает false если клиента с таким адресом нет в таблице
+    pub fn touch(&mut self, address: &SocketAddr) -> bool {
+        match self.sessions.get_mut(address) {
+            Some(session) => {
+                session.last_seen = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    //Идентификатор клиента с этим адресом
+    pub fn id(&self, address: &SocketAddr) -> Option<u32> {
+        self.sessions.get(address).map(|session| session.id)
+    }
+
+    //Ник клиента с этим адресом
+    pub fn nickname(&self, address: &SocketAddr) -> Option<&str> {
+        self.sessions.get(address).map(|session| session.nickname.as_str())
+    }
+
+    //Токен сессии клиента с этим адресом
+    pub fn token(&self, address: &SocketAddr) -> Option<&str> {
+        self.sessions.get(address).map(|session| session.token.as_str())
+    }
+
+    //Адрес клиента сессия которого имеет этот токен
+    pub fn find_token(&self, token: &str) -> Option<SocketAddr> {
+        self.sessions
+            .iter()
+            .find(|&(_, session)| session.token == token)
+            .map(|(address, _)| *address)
+    }
+
+    //Время из пакета Join которым клиент с этим адресом вошел в чат
+    pub fn join_timestamp(&self, address: &SocketAddr) -> Option<u64> {
+        self.sessions.get(address).map(|session| session.join_timestamp)
+    }
+
+    //Адреса всех клиентов вошедших в чат
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.sessions.keys().cloned().collect()
+    }
+
+    //Количество клиентов вошедших в чат
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    //Занят ли ник кем то из клиентов. Ники сравниваются без учета регистра
+    pub fn is_taken(&self, nickname: &str) -> bool {
+        self.find(nickname).is_some()
+    }
+
+    //Ищет клиента по нику без учета регистра и возвращает его адрес и ник
+    pub fn find(&self, nickname: &str) -> Option<(SocketAddr, &str)> {
+        self.sessions
+            .iter()
+            .find(|&(_, session)| session.nickname.to_lowercase() == nickname.to_lowercase())
+            .map(|(address, session)| (*address, session.nickname.as_str()))
+    }
+
+    //Выдает номер для следующего пакета отправляемого клиенту.
+    //У каждого клиента своя нумерация чтобы он мог заметить пропуски
+    pub fn next_sequence(&mut self, address: &SocketAddr) -> Option<u32> {
+        self.sessions.get_mut(address).map(|session| {
+            session.sequence = session.sequence.wrapping_add(1);
+            session.sequence
+        })
+    }
+
+    //Отмечает что клиенту не удалось отправить датаграмму и возвращает сколько всего было таких неудач
+    pub fn record_send_failure(&mut self, address: &SocketAddr) -> Option<u32> {
+        self.sessions.get_mut(address).map(|session| {
+            session.send_failures += 1;
+            session.send_failures
+        })
+    }
+
+    //Удаляет клиента из таблицы. Возвращает его ник если он там был
+    pub fn remove(&mut self, address: &SocketAddr) -> Option<String> {
+        self.sessions.remove(address).map(|session| session.nickname)
+    }
+
+    //Удаляет клиента из таблицы, но запоминает его сессию вместе с комнатами rooms,
+    // чтобы клиент мог ее восстановить по токену. Возвращает ник клиента если он был в таблице
+    pub fn suspend(&mut self, address: &SocketAddr, rooms: Vec<String>) -> Option<String> {
+        let session = self.sessions.remove(address)?;
+        self.suspended.insert(session.token, Suspended {
+            nickname: session.nickname.clone(),
+            rooms,
+            since: Instant::now(),
+        });
+        Some(session.nickname)
+    }
+
+    //Забирает сессию пропавшего клиента по ее токену и возвращает его ник и комнаты
+    pub fn take_suspended(&mut self, token: &str) -> Option<(String, Vec<String>)> {
+        self.suspended.remove(token).map(|suspended| (suspended.nickname, suspended.rooms))
+    }
+
+    //Адреса клиентов от которых не было данных дольше чем idle_timeout.
+    //Заодно забывает сессии пропавших клиентов которые так и не вернулись
+    pub fn idle(&mut self, idle_timeout: Duration) -> Vec<SocketAddr> {
+        self.suspended.retain(|_, suspended| suspended.since.elapsed() < Duration::from_secs(RESUME_WINDOW_IN_SECS));
+        let now = Instant::now();
+        self.sessions
+            .iter()
+            .filter(|&(_, session)| now.duration_since(session.last_seen) > idle_timeout)
+            .map(|(address, _)| *address)
+            .collect()
+    }
+}
+
+impl Default for Sessions {
+    fn default() -> Sessions {
+        Sessions::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    //Время на seconds секунд раньше текущего
+    fn ago(seconds: u64) -> Instant {
+        Instant::now().checked_sub(Duration::from_secs(seconds)).unwrap()
+    }
+
+    #[test]
+    fn finds_idle_clients() {
+        let mut sessions = Sessions::new();
+        sessions.join(address(1), "alice".to_string(), 0);
+        sessions.join(address(2), "bob".to_string(), 0);
+        sessions.sessions.get_mut(&address(1)).unwrap().last_seen = ago(61);
+        sessions.sessions.get_mut(&address(2)).unwrap().last_seen = ago(59);
+        assert_eq!(sessions.idle(Duration::from_secs(60)), [address(1)]);
+        //Пакет от клиента снова делает его активным
+        assert!(sessions.touch(&address(1)));
+        assert!(sessions.idle(Duration::from_secs(60)).is_empty());
+        assert!(!sessions.touch(&address(3)));
+    }
+
+    #[test]
+    fn resumes_suspended_session_by_token() {
+        let mut sessions = Sessions::new();
+        sessions.join(address(1), "alice".to_string(), 0);
+        let token = sessions.token(&address(1)).unwrap().to_string();
+        assert_eq!(sessions.suspend(&address(1), vec!["general".to_string()]), Some("alice".to_string()));
+        assert_eq!(sessions.len(), 0);
+        assert!(!sessions.is_taken("alice"));
+        //Недавно пропавшего клиента idle не забывает
+        sessions.idle(Duration::from_secs(60));
+        assert_eq!(sessions.take_suspended("wrong token"), None);
+        assert_eq!(sessions.take_suspended(&token), Some(("alice".to_string(), vec!["general".to_string()])));
+        //Сессию можно восстановить только один раз
+        assert_eq!(sessions.take_suspended(&token), None);
+    }
+
+    #[test]
+    fn forgets_suspended_session_after_resume_window() {
+        let mut sessions = Sessions::new();
+        sessions.join(address(1), "alice".to_string(), 0);
+        let token = sessions.token(&address(1)).unwrap().to_string();
+        sessions.suspend(&address(1), Vec::new());
+        sessions.suspended.get_mut(&token).unwrap().since = ago(RESUME_WINDOW_IN_SECS + 1);
+        sessions.idle(Duration::from_secs(60));
+        assert_eq!(sessions.take_suspended(&token), None);
+    }
+
+    #[test]
+    fn nicknames_are_case_insensitive() {
+        let mut sessions = Sessions::new();
+        let id = sessions.join(address(1), "Alice".to_string(), 0);
+        assert!(sessions.is_taken("alice"));
+        assert!(sessions.is_taken("ALICE"));
+        assert!(!sessions.is_taken("bob"));
+        assert_eq!(sessions.find("aLiCe"), Some((address(1), "Alice")));
+        assert_eq!(sessions.id(&address(1)), Some(id));
+        assert_ne!(sessions.join(address(2), "bob".to_string(), 0), id);
+        assert_eq!(sessions.remove(&address(1)), Some("Alice".to_string()));
+        assert!(!sessions.is_taken("alice"));
+    }
+}
@@ -0,0 +1,137 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use protocol::{Datagram, Identity, Sealer};
+use tokio::net::UdpSocket;
+use tokio::time;
+
+use crate::auth::Auth;
+use crate::config::Config;
+use crate::error::ServerError;
+use crate::events::Subscribers;
+use crate::history::History;
+use crate::{Broadcaster, TICK_IN_MILLIS};
+
+//Сколько датаграмм отправляет одна задача. Рассылка большому числу клиентов делится
+// между несколькими задачами которые выполняются параллельно на потоках tokio
+const FAN_OUT_CHUNK: usize = 64;
+
+//Датаграмма которую еще предстоит отправить
+struct Outgoing {
+    //Ключ и номер если датаграмму еще нужно зашифровать
+    sealer: Option<Sealer>,
+    bytes: Vec<u8>,
+    address: SocketAddr,
+}
+
+impl Outgoing {
+    //Готовая к отправке датаграмма. Шифрование здесь, а не в Outbox, чтобы оно шло в задачах рассылки
+    fn into_datagram(self) -> Vec<u8> {
+        match self.sealer {
+            Some(sealer) => sealer.seal(&self.bytes),
+            None => self.bytes,
+        }
+    }
+}
+
+//Датаграммы которые Broadcaster хочет отправить. Вместо того чтобы отправлять их по одной
+// он складывает их сюда, а после обработки очередной датаграммы они рассылаются все вместе
+#[derive(Default)]
+struct Outbox {
+    datagrams: Mutex<Vec<Outgoing>>,
+}
+
+impl Outbox {
+    //Забирает все накопленные датаграммы
+    fn take(&self) -> Vec<Outgoing> {
+        self.datagrams
+            .lock()
+            .map(|mut datagrams| datagrams.drain(..).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Datagram for Outbox {
+    fn send_to(&self, buf: &[u8], address: SocketAddr) -> io::Result<usize> {
+        if let Ok(mut datagrams) = self.datagrams.lock() {
+            datagrams.push(Outgoing { sealer: None, bytes: buf.to_vec(), address });
+        }
+        Ok(buf.len())
+    }
+
+    //Номер датаграмме выдан, а шифрует ее уже задача рассылки
+    fn send_sealed(&self, sealer: Sealer, buf: &[u8], address: SocketAddr) -> io::Result<usize> {
+        if let Ok(mut datagrams) = self.datagrams.lock() {
+            datagrams.push(Outgoing { sealer: Some(sealer), bytes: buf.to_vec(), address });
+        }
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, _buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        Err(io::Error::other("outbox can't receive datagrams"))
+    }
+}
+
+//Принимает датаграммы и рассылает ответы пока не будет поднят флаг stop.
+//Вся логика чата та же что и у сервера на потоках, отличается только рассылка
+pub async fn serve(socket: UdpSocket,
+                   identity: Identity,
+                   auth: Auth,
+                   history: History,
+                   config: Config,
+                   subscribers: Subscribers,
+                   stop: Arc<AtomicBool>) -> Result<(), ServerError> {
+    let socket = Arc::new(socket);
+    let mut broadcaster = Broadcaster::new(Outbox::default(), identity, auth, history, &config, subscribers);
+    //Регулярно проверяем молчащих клиентов и неподтвержденные пакеты
+    let mut tick = time::interval(Duration::from_millis(TICK_IN_MILLIS));
+    let mut buf = [0u8; 4096];
+    while !stop.load(Ordering::SeqCst) {
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => match received {
+                //Пустую датаграмму присылает ServerHandle::shutdown чтобы разбудить сервер
+                Ok(_) if stop.load(Ordering::SeqCst) => break,
+                Ok((count, source)) => broadcaster.handle_datagram(&buf[..count], source),
+                Err(e) => warn!("can't read from socket: {}", e),
+            },
+            _ = tick.tick() => {
+                broadcaster.retransmit();
+                broadcaster.evict_idle();
+            }
+        }
+        fan_out(&socket, broadcaster.socket.inner().take());
+    }
+    //Оповещение об остановке отправляем здесь же, а не в задачах которые остановятся вместе с рантаймом
+    let flushed = broadcaster.shutdown();
+    for outgoing in broadcaster.socket.inner().take() {
+        let address = outgoing.address;
+        if let Err(e) = socket.send_to(&outgoing.into_datagram(), address).await {
+            warn!("{}", ServerError::Send(address, e));
+        }
+    }
+    flushed
+}
+
+//Шифрует и рассылает датаграммы параллельно в нескольких задачах, чтобы время рассылки
+// не росло линейно с количеством клиентов
+fn fan_out(socket: &Arc<UdpSocket>, datagrams: Vec<Outgoing>) {
+    let mut datagrams = datagrams.into_iter();
+    loop {
+        let chunk: Vec<Outgoing> = datagrams.by_ref().take(FAN_OUT_CHUNK).collect();
+        if chunk.is_empty() {
+            return;
+        }
+        let socket = socket.clone();
+        tokio::spawn(async move {
+            for outgoing in chunk {
+                let address = outgoing.address;
+                if let Err(e) = socket.send_to(&outgoing.into_datagram(), address).await {
+                    warn!("{}", ServerError::Send(address, e));
+                }
+            }
+        });
+    }
+}
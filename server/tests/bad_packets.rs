@@ -0,0 +1,247 @@
+//Сервер не должен падать и переставать обслуживать клиентов из за испорченных датаграмм
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use protocol::{Datagram, Kind, Packet, Reliable, Secure};
+use server::{ChatServer, Level, ServerHandle};
+
+//Сколько ждать ответа сервера
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(3);
+
+//Клиент который подтверждает пакеты сервера и позволяет отправить ему что угодно
+struct Client {
+    socket: Secure<UdpSocket>,
+    server: SocketAddr,
+    reliable: Reliable,
+    sequence: u32,
+}
+
+impl Client {
+    fn connect(handle: &ServerHandle) -> Client {
+        let server = handle.local_addr();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(server).unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        let socket = Secure::connect(socket, server, Some(handle.fingerprint())).unwrap();
+        Client { socket, server, reliable: Reliable::new(), sequence: 0 }
+    }
+
+    fn send(&mut self, kind: Kind, payload: Vec<u8>) {
+        self.sequence += 1;
+        let packet = Packet::new(kind, protocol::SERVER_ID, self.sequence, payload);
+        self.reliable.send(&self.socket, self.server, &packet).unwrap();
+    }
+
+    //Шифрует и отправляет произвольные байты вместо пакета
+    fn send_raw(&self, bytes: &[u8]) {
+        self.socket.send_to(bytes, self.server).unwrap();
+    }
+
+    fn join(&mut self, nickname: &str) {
+        self.join_with(nickname, "");
+    }
+
+    fn join_with(&mut self, nickname: &str, password: &str) {
+        self.send(Kind::Join, protocol::encode_fields(&[nickname, password]));
+        self.expect(Kind::Accepted);
+    }
+
+    //Все новые пакеты которые пришли за время duration
+    fn receive_for(&mut self, duration: Duration) -> Vec<Packet> {
+        let started = Instant::now();
+        let mut packets = Vec::new();
+        let mut buf = [0u8; 4096];
+        while started.elapsed() < duration {
+            match self.socket.recv_from(&mut buf) {
+                Ok((count, _)) => {
+                    let packet = protocol::decode(&buf[..count]).unwrap();
+                    if self.reliable.receive(&self.socket, self.server, &packet).unwrap() {
+                        packets.push(packet);
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                Err(e) => panic!("can't read: {}", e),
+            }
+            self.reliable.retransmit(&self.socket);
+        }
+        packets
+    }
+
+    //Ждет пакет типа kind пропуская остальные
+    fn expect(&mut self, kind: Kind) -> Packet {
+        let started = Instant::now();
+        while started.elapsed() < ANSWER_TIMEOUT {
+            let packets = self.receive_for(Duration::from_millis(50));
+            if let Some(packet) = packets.into_iter().find(|packet| packet.kind == kind) {
+                return packet;
+            }
+        }
+        panic!("server didn't send {:?}", kind)
+    }
+
+    //Ждет служебное оповещение и возвращает его текст
+    fn expect_notice(&mut self) -> String {
+        let fields = self.expect(Kind::Notice).fields().unwrap();
+        fields[1].clone()
+    }
+}
+
+fn start() -> ServerHandle {
+    ChatServer::new()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .log_level(Level::Error)
+        .spawn()
+        .unwrap()
+}
+
+//Сервер все еще пускает новых клиентов и рассылает их сообщения
+fn assert_serving(handle: &ServerHandle) {
+    let mut client = Client::connect(handle);
+    client.join("checker");
+    client.send(Kind::Message, protocol::encode_fields(&[protocol::DEFAULT_ROOM, "still alive"]));
+    let fields = client.expect(Kind::Message).fields().unwrap();
+    assert_eq!(fields, [protocol::DEFAULT_ROOM, "checker", "still alive"]);
+}
+
+#[test]
+fn survives_garbage_and_truncated_header() {
+    let handle = start();
+    let mut client = Client::connect(&handle);
+    client.send_raw(b"");
+    client.send_raw(b"garbage that is not a packet");
+    let encoded = protocol::encode(&Packet::new(Kind::Message, 0, 1, b"hello".to_vec()));
+    client.send_raw(&encoded[..10]);
+    //Длина данных в заголовке больше чем пришло
+    client.send_raw(&encoded[..encoded.len() - 1]);
+    //Неизвестный тип пакета
+    let mut unknown = encoded.clone();
+    unknown[1] = 250;
+    client.send_raw(&unknown);
+    //Пакеты которые не удалось разобрать сервер пропускает молча
+    assert!(client.receive_for(Duration::from_millis(300)).is_empty());
+    client.join("alice");
+    assert_serving(&handle);
+    handle.shutdown().unwrap();
+}
+
+#[test]
+fn rejects_bad_join_payload() {
+    let handle = start();
+    let mut client = Client::connect(&handle);
+    client.send(Kind::Join, b"\xff\xff\xff\xff not fields".to_vec());
+    assert_eq!(client.expect(Kind::Rejected).text().unwrap(), "invalid Join packet");
+    client.send(Kind::Join, protocol::encode_fields(&["only nickname"]));
+    assert_eq!(client.expect(Kind::Rejected).text().unwrap(), "invalid Join packet");
+    //После отказа можно войти заново
+    client.join("alice");
+    assert_serving(&handle);
+    handle.shutdown().unwrap();
+}
+
+#[test]
+fn notifies_joined_client_about_bad_payload() {
+    let handle = start();
+    let mut client = Client::connect(&handle);
+    client.join("alice");
+    client.send(Kind::Message, protocol::encode_fields(&["only room"]));
+    assert_eq!(client.expect_notice(), "invalid Message packet");
+    client.send(Kind::RoomJoin, vec![0xff, 0xfe]);
+    assert_eq!(client.expect_notice(), "invalid RoomJoin packet");
+    client.send(Kind::Private, Vec::new());
+    assert_eq!(client.expect_notice(), "invalid Private packet");
+    //Клиент остался в чате
+    client.send(Kind::Message, protocol::encode_fields(&[protocol::DEFAULT_ROOM, "hi"]));
+    assert_eq!(client.expect(Kind::Message).fields().unwrap()[2], "hi");
+    handle.shutdown().unwrap();
+}
+
+#[test]
+fn survives_undecryptable_datagrams() {
+    let handle = start();
+    let server = handle.local_addr();
+    //Датаграммы от того кто не обменивался ключами
+    let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for bytes in [&b""[..], b"\x00", b"\x02\x03 sealed without keys", &[0xff; 1500]] {
+        stranger.send_to(bytes, server).unwrap();
+    }
+    //Поддельная зашифрованная датаграмма от клиента у которого есть ключи
+    let mut client = Client::connect(&handle);
+    let mut forged = vec![protocol::VERSION, 3];
+    forged.extend_from_slice(&[0u8; 64]);
+    client.socket.inner().send(&forged).unwrap();
+    assert!(client.receive_for(Duration::from_millis(300)).is_empty());
+    //Подделка не испортила канал клиента
+    client.join("alice");
+    assert_serving(&handle);
+    handle.shutdown().unwrap();
+}
+
+#[test]
+fn requires_join_before_commands() {
+    let handle = ChatServer::new()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .log_level(Level::Error)
+        .token("secret")
+        .spawn()
+        .unwrap();
+    let mut alice = Client::connect(&handle);
+    alice.join_with("alice", "secret");
+    //Тот кто только обменялся ключами, но не вошел в чат, ничего не может сделать от имени участника
+    let mut stranger = Client::connect(&handle);
+    let commands = vec![
+        (Kind::Private, protocol::encode_fields(&["alice", "hi from nobody"])),
+        (Kind::Message, protocol::encode_fields(&[protocol::DEFAULT_ROOM, "hi from nobody"])),
+        (Kind::RoomJoin, protocol::DEFAULT_ROOM.as_bytes().to_vec()),
+        (Kind::RoomLeave, protocol::DEFAULT_ROOM.as_bytes().to_vec()),
+        (Kind::RoomList, Vec::new()),
+    ];
+    for (kind, payload) in commands {
+        stranger.send(kind, payload);
+        assert_eq!(stranger.expect(Kind::Rejected).text().unwrap(), "join the chat first", "{:?}", kind);
+    }
+    let received = alice.receive_for(Duration::from_millis(300));
+    assert!(received.iter().all(|packet| packet.kind != Kind::Private && packet.kind != Kind::Notice
+        && packet.kind != Kind::Message), "{:?}", received);
+    //С паролем тот же клиент входит как обычно
+    stranger.join_with("bob", "secret");
+    assert_eq!(alice.expect_notice(), "bob joined the chat");
+    handle.shutdown().unwrap();
+}
+
+fn start_with_token(token: &str) -> ServerHandle {
+    ChatServer::new()
+        .bind("127.0.0.1:0".parse().unwrap())
+        .log_level(Level::Error)
+        .token(token)
+        .spawn()
+        .unwrap()
+}
+
+#[test]
+fn rejects_wrong_password() {
+    let handle = start_with_token("secret");
+    let mut client = Client::connect(&handle);
+    client.send(Kind::Join, protocol::encode_fields(&["alice", "guess"]));
+    assert_eq!(client.expect(Kind::Rejected).text().unwrap(), "wrong nickname or password");
+    //После отказа можно войти с правильным паролем
+    client.join_with("alice", "secret");
+    handle.shutdown().unwrap();
+}
+
+#[test]
+fn bans_address_after_failed_logins() {
+    let handle = start_with_token("secret");
+    let mut client = Client::connect(&handle);
+    for _ in 0..5 {
+        client.send(Kind::Join, protocol::encode_fields(&["alice", "guess"]));
+        assert_eq!(client.expect(Kind::Rejected).text().unwrap(), "wrong nickname or password");
+    }
+    //Заблокированному адресу не помогает даже правильный пароль, в том числе с нового порта
+    client.send(Kind::Join, protocol::encode_fields(&["alice", "secret"]));
+    assert_eq!(client.expect(Kind::Rejected).text().unwrap(), "too many failed logins, try again later");
+    let mut other = Client::connect(&handle);
+    other.send(Kind::Join, protocol::encode_fields(&["bob", "secret"]));
+    assert_eq!(other.expect(Kind::Rejected).text().unwrap(), "too many failed logins, try again later");
+    handle.shutdown().unwrap();
+}